@@ -4,7 +4,7 @@
 //!  the metric are available on `0.0.0.0:9000` for inspection
 
 #[cfg(feature = "tokio")]
-async fn main() {
+fn main() {
     eprintln!("This example is not available when the Tokio feature is enabled.");
 }
 
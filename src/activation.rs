@@ -0,0 +1,51 @@
+//! Systemd socket activation: taking over listening sockets bound by the service manager.
+//!
+//! See `sd_listen_fds(3)`. The service manager passes the sockets as consecutive file
+//! descriptors starting at 3, with `LISTEN_FDS` holding their number and `LISTEN_PID` the
+//! process they are meant for. Like `sd_listen_fds(3)` with `unset_environment` set, taking the
+//! sockets unsets those variables so child processes do not try to take them too.
+
+use std::{
+    env,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The first file descriptor passed by the service manager.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Set once the passed sockets have been taken, so they are never owned twice.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Takes the sockets passed by the service manager, if they were passed to this process.
+///
+/// Only the first call returns them; later calls return nothing. The descriptors are marked
+/// close-on-exec, as the service manager passes them inheritable.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|count| for_us && *count > 0)
+        .unwrap_or(0);
+    if count == 0 || TAKEN.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        // SAFETY: as with `sd_listen_fds(3)`, this must not race other threads reading the
+        // environment, which the callers of `IPCCollector::from_listen_fds` are told.
+        unsafe { env::remove_var(var) };
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: `fd` is open, and setting its flags has no other precondition.
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            // SAFETY: the service manager passed these descriptors to this process for it to
+            // own, and `TAKEN` ensures they are only ever wrapped once.
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        })
+        .collect()
+}
@@ -0,0 +1,55 @@
+//! Peer credential checks for connections accepted by the collector.
+//!
+//! On Unix the kernel reports the uid, gid and pid of the process at the other end of a local
+//! socket. The collector compares those against a [`PeerAllowlist`] before reading any events.
+
+use interprocess::local_socket::PeerCreds;
+
+/// The uids, gids and pids allowed to send metrics to the collector.
+///
+/// An empty allowlist accepts every peer. Otherwise a peer is accepted if any one of its
+/// credentials is listed.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAllowlist {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+    pub pids: Vec<u32>,
+}
+
+impl PeerAllowlist {
+    pub const fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty() && self.pids.is_empty()
+    }
+
+    /// Checks the credentials of a peer against the allowlist.
+    pub fn permits(&self, creds: &PeerCreds) -> bool {
+        let listed = |ids: &[u32], id: Option<u32>| id.is_some_and(|id| ids.contains(&id));
+
+        listed(&self.uids, creds.euid())
+            || listed(&self.gids, creds.egid())
+            || listed(
+                &self.pids,
+                creds.pid().and_then(|pid| u32::try_from(pid).ok()),
+            )
+    }
+
+    /// Decides whether a connection should be accepted, logging rejections.
+    ///
+    /// Peers whose credentials cannot be read are rejected unless the allowlist is empty.
+    pub fn authorize(&self, creds: &std::io::Result<PeerCreds>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        match creds {
+            Ok(creds) if self.permits(creds) => true,
+            Ok(creds) => {
+                log::warn!("Rejected metrics connection from unauthorized peer {creds:?}");
+                false
+            }
+            Err(e) => {
+                log::warn!("Rejected metrics connection, could not read peer credentials: {e}");
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Capture files recording every frame received by the collector, for later replay.
+//!
+//! A capture file starts with the 8 byte header `MIPCCAP1`, followed by one record per frame: the
+//! receive time in nanoseconds since the Unix epoch and the connection id as little-endian
+//! `u64`s, the length of the connection's labels and of the frame as little-endian `u32`s, the
+//! labels, then the frame's payload exactly as it was received, without any framing header.
+//!
+//! The labels the collector added to a connection's metrics, such as peer and listener labels,
+//! are msgpack encoded in the connection's first record in each file, and left empty after that.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Identifies a capture file and its format version.
+const MAGIC: &[u8; 8] = b"MIPCCAP1";
+
+/// The size of the fixed part of a record, before the labels and frame.
+const RECORD_HEADER: usize = 8 + 8 + 4 + 4;
+
+/// Where frames are captured and when the capture file is rotated.
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// The capture file, or `None` if frames are not captured.
+    pub path: Option<PathBuf>,
+    /// The size a capture file may reach before it is rotated.
+    pub max_bytes: u64,
+    /// The number of rotated files kept, named `<path>.1` (newest) to `<path>.<keep>` (oldest).
+    pub keep: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 64 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+/// Appends records to a capture file, rotating it once it grows too large.
+#[derive(Debug)]
+pub struct CaptureWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+    /// Connections whose labels have been written to the current file.
+    labelled: HashSet<u64>,
+}
+
+impl CaptureWriter {
+    /// Opens the configured capture file for appending, creating it if it does not exist.
+    ///
+    /// Returns `None` if no capture file is configured.
+    pub fn open(config: CaptureConfig) -> io::Result<Option<Self>> {
+        let Some(path) = config.path else {
+            return Ok(None);
+        };
+        let (file, written) = open_for_append(&path)?;
+        Ok(Some(Self {
+            path,
+            max_bytes: config.max_bytes,
+            keep: config.keep,
+            file,
+            written,
+            labelled: HashSet::new(),
+        }))
+    }
+
+    /// Appends a frame received on `connection_id` at `received_at`, along with the `labels` added
+    /// to the connection's metrics unless they are already in the current file.
+    pub fn write(
+        &mut self,
+        received_at: SystemTime,
+        connection_id: u64,
+        labels: &BTreeMap<String, String>,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let length = |bytes: &[u8]| {
+            u32::try_from(bytes.len())
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame too large to capture"))
+        };
+        let nanos = received_at.duration_since(UNIX_EPOCH).map_or(0, |since| {
+            u64::try_from(since.as_nanos()).unwrap_or(u64::MAX)
+        });
+        let encoded = if labels.is_empty() {
+            Vec::new()
+        } else {
+            rmp_serde::to_vec(labels).map_err(io::Error::other)?
+        };
+
+        let labels_size = if self.labelled.contains(&connection_id) {
+            0
+        } else {
+            encoded.len()
+        };
+        let size = (RECORD_HEADER + labels_size + frame.len()) as u64;
+        if self.written > MAGIC.len() as u64 && self.written + size > self.max_bytes {
+            self.rotate()?;
+        }
+        // Rotating starts a file that needs the labels of every connection again.
+        let labels = if self.labelled.contains(&connection_id) {
+            &[][..]
+        } else {
+            &encoded[..]
+        };
+
+        let mut record = Vec::with_capacity(RECORD_HEADER + labels.len() + frame.len());
+        record.extend_from_slice(&nanos.to_le_bytes());
+        record.extend_from_slice(&connection_id.to_le_bytes());
+        record.extend_from_slice(&length(labels)?.to_le_bytes());
+        record.extend_from_slice(&length(frame)?.to_le_bytes());
+        record.extend_from_slice(labels);
+        record.extend_from_slice(frame);
+
+        // One write per record, so an interrupted write can only truncate the last record.
+        self.file.write_all(&record)?;
+        self.written += record.len() as u64;
+        if !labels.is_empty() {
+            self.labelled.insert(connection_id);
+        }
+        Ok(())
+    }
+
+    /// Forgets a closed connection, whose labels will not be needed again.
+    pub fn close(&mut self, connection_id: u64) {
+        self.labelled.remove(&connection_id);
+    }
+
+    /// Shifts every rotated file up by one, moves the current file to `<path>.1` and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match std::fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        (self.file, self.written) = open_for_append(&self.path)?;
+        self.labelled.clear();
+        Ok(())
+    }
+}
+
+/// Opens `path` for appending, writing the file header if it is new, and returns its size.
+fn open_for_append(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut written = file.metadata()?.len();
+    if written == 0 {
+        file.write_all(MAGIC)?;
+        written = MAGIC.len() as u64;
+    }
+    Ok((file, written))
+}
+
+/// A frame read back from a capture file.
+#[derive(Debug)]
+pub struct CapturedFrame {
+    pub received_at: SystemTime,
+    pub connection_id: u64,
+    /// The labels added to the connection's metrics, recorded with its first frame in the file.
+    pub labels: Option<BTreeMap<String, String>>,
+    pub frame: Vec<u8>,
+}
+
+/// Reads the frames of a capture file in the order they were received.
+pub struct CaptureReader {
+    reader: BufReader<File>,
+}
+
+impl CaptureReader {
+    /// Opens a capture file, checking that it starts with the capture header.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a metrics capture file",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        let mut header = [0; RECORD_HEADER];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let (nanos, rest) = header.split_at(8);
+        let (connection_id, rest) = rest.split_at(8);
+        let (labels_length, length) = rest.split_at(4);
+        let nanos = u64::from_le_bytes(nanos.try_into().unwrap_or_default());
+        let connection_id = u64::from_le_bytes(connection_id.try_into().unwrap_or_default());
+        let labels_length = u32::from_le_bytes(labels_length.try_into().unwrap_or_default());
+        let length = u32::from_le_bytes(length.try_into().unwrap_or_default());
+
+        let mut labels = vec![0; labels_length as usize];
+        let mut frame = vec![0; length as usize];
+        // The collector may have stopped part way through writing the last record.
+        if !read_or_eof(&mut self.reader, &mut labels)?
+            || !read_or_eof(&mut self.reader, &mut frame)?
+        {
+            return Ok(None);
+        }
+        let labels = if labels.is_empty() {
+            None
+        } else {
+            Some(
+                rmp_serde::from_slice(&labels)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            )
+        };
+        Ok(Some(CapturedFrame {
+            received_at: UNIX_EPOCH + Duration::from_nanos(nanos),
+            connection_id,
+            labels,
+            frame,
+        }))
+    }
+}
+
+/// Fills `buffer`, returning `false` if the file ends first.
+fn read_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = io::Result<CapturedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
@@ -0,0 +1,107 @@
+//! Limits on the number of series the collector forwards to its sink.
+//!
+//! A client that puts unbounded values such as request ids into labels would otherwise create a
+//! new series in the exporter for every value it sends.
+
+use crate::{events::MetricData, expiry::SeriesKey};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// The label value used for series collapsed by [`CardinalityOverflow::Collapse`].
+const OTHER: &str = "other";
+
+/// What the collector does with a new series once a cardinality limit has been reached.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{CardinalityOverflow, IPCCollector};
+/// let collector = IPCCollector::default()
+///     .max_series_per_metric(1_000)
+///     .cardinality_overflow(CardinalityOverflow::Collapse);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardinalityOverflow {
+    /// Drop updates to the new series. This is the default.
+    #[default]
+    Drop,
+    /// Replace every label value of the new series with `other`, so all overflowing series of a
+    /// metric are reported as one.
+    Collapse,
+}
+
+#[derive(Debug, Default)]
+struct Admitted {
+    /// Every series admitted so far. Collapsed series are not included.
+    series: HashSet<SeriesKey>,
+    /// Number of admitted series per metric name.
+    per_metric: HashMap<String, usize>,
+}
+
+/// Admits new series while they fit within the configured limits.
+#[derive(Debug, Default)]
+pub struct SeriesLimits {
+    pub max_series: Option<usize>,
+    pub max_series_per_metric: Option<usize>,
+    pub overflow: CardinalityOverflow,
+    admitted: Mutex<Admitted>,
+}
+
+impl SeriesLimits {
+    /// Returns `false` if no limit is configured, in which case nothing needs tracking.
+    pub const fn is_enabled(&self) -> bool {
+        self.max_series.is_some() || self.max_series_per_metric.is_some()
+    }
+
+    /// Checks a metric against the limits, returning the metric to forward to the sink, if any,
+    /// and whether its series was rejected.
+    pub fn apply(&self, mut metric: MetricData) -> (Option<MetricData>, bool) {
+        if !self.is_enabled() {
+            return (Some(metric), false);
+        }
+
+        let key = SeriesKey::from(&metric);
+        let mut admitted = self.admitted.lock().unwrap();
+        if admitted.series.contains(&key) {
+            return (Some(metric), false);
+        }
+        let per_metric = admitted.per_metric.get(&key.name).copied().unwrap_or(0);
+        let within_limits = self
+            .max_series
+            .is_none_or(|max| admitted.series.len() < max)
+            && self
+                .max_series_per_metric
+                .is_none_or(|max| per_metric < max);
+        if within_limits {
+            *admitted.per_metric.entry(key.name.clone()).or_default() += 1;
+            admitted.series.insert(key);
+            return (Some(metric), false);
+        }
+
+        match self.overflow {
+            CardinalityOverflow::Drop => (None, true),
+            CardinalityOverflow::Collapse => {
+                for value in metric.labels.values_mut() {
+                    OTHER.clone_into(value);
+                }
+                drop(admitted);
+                (Some(metric), true)
+            }
+        }
+    }
+
+    /// Frees the slot of a series that has been removed from the sink.
+    pub fn remove(&self, key: &SeriesKey) {
+        let mut admitted = self.admitted.lock().unwrap();
+        if !admitted.series.remove(key) {
+            return;
+        }
+        if let Some(count) = admitted.per_metric.get_mut(&key.name) {
+            *count -= 1;
+            if *count == 0 {
+                admitted.per_metric.remove(&key.name);
+            }
+        }
+    }
+}
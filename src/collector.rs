@@ -8,27 +8,45 @@
 //!
 //! See crate-level docs and README for details.
 
+#[cfg(unix)]
 use crate::{
+    activation,
+    peer::{self, PeerLabel},
+};
+use crate::{
+    capture::{CaptureConfig, CaptureReader, CaptureWriter, CapturedFrame},
+    cardinality::{CardinalityOverflow, SeriesLimits},
+    conflict::{ConflictHandler, MetadataConflict, MetadataRegistry},
     error::MetricsError,
-    events::{MetricData, MetricEvent, MetricKind, MetricMetadata, MetricOperation},
+    events::{MetricEvent, MetricMetadata},
+    expiry::{self, DisconnectPolicy, SeriesKey, SeriesTracker},
+    handle::{CollectorHandle, ReceivedEvent, Subscribers},
+    listener::{ConnectionPolicy, ListenerConfig},
+    merge::{CounterTotals, GaugeMerge, GaugeMerger, Source},
+    pattern::{NameFilter, NamePattern},
+    relabel::{RelabelRule, RelabelRules},
+    sink::{GlobalRecorderSink, MetricSink},
+    socket::SocketPath,
+    telemetry::Telemetry,
 };
-#[cfg(feature = "tokio")]
-use interprocess::local_socket::tokio::prelude::*;
-use interprocess::local_socket::{GenericFilePath, GenericNamespaced, ListenerOptions};
-#[cfg(not(feature = "tokio"))]
-use interprocess::local_socket::{ListenerNonblockingMode, Stream, prelude::*};
-use std::path::PathBuf;
-#[cfg(not(feature = "tokio"))]
+use interprocess::local_socket::PeerCreds;
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
 use std::{
-    io::{BufRead, BufReader},
-    thread,
-};
-#[cfg(feature = "tokio")]
-use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    task,
+    collections::{BTreeMap, HashMap, HashSet, hash_map::Entry},
+    net::IpAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
+/// The largest frame accepted unless changed with [`IPCCollector::max_frame_size`].
+const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
 /// Collects metrics from multiple processes via IPC.
 ///
 /// The `IPCCollector` listens on a local socket for incoming metric events from other processes.
@@ -37,39 +55,26 @@ use tokio::{
 /// # Examples
 ///
 /// Basic usage:
-/// ```rust
+/// ```rust,no_run
 /// use metrics_ipc_collector::IPCCollector;
 /// let collector = IPCCollector::default();
-/// collector.start_collecting()?;
+/// let handle = collector.start_collecting()?;
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
 /// ```
 ///
 /// See [`start_collecting`](#method.start_collecting) for more details and error handling.
 ///
-/// # Feature Flags
-/// - `tokio`: Enables async support. Requires a Tokio runtime.
-///
-/// # Errors
-/// Creating the collector may fail if the socket file cannot be created or removed.
-///
-/// # See Also
-/// - [`IPCRecorderBuilder`](crate::recorder::IPCRecorderBuilder)
-/// - [`MetricsError`](crate::error::MetricsError)
-///
-/// Collects metrics from multiple processes via IPC.
-///
-/// The `IPCCollector` listens on a local socket for incoming metric events from other processes.
-/// It supports both synchronous (threaded) and asynchronous (Tokio) operation, depending on the crate feature flags.
-///
-/// # Examples
-///
-/// Basic usage:
-/// ```rust
-/// use metrics_ipc_collector::IPCCollector;
-/// let collector = IPCCollector::default();
-/// collector.start_collecting()?;
-/// ```
+/// # Merging
+/// Absolute counter values, set with [`Counter::absolute`](metrics::Counter::absolute), are
+/// tracked per source and exported as the sum of every source's total. A total that goes
+/// backwards is treated as the process having restarted. Gauges are merged according to the
+/// rules added with [`gauge_merge`](Self::gauge_merge).
 ///
-/// See [`start_collecting`](#method.start_collecting) for more details and error handling.
+/// A source is a client id sent with [`IPCRecorderBuilder::client_id`](crate::IPCRecorderBuilder::client_id),
+/// or the connection itself for clients that do not identify themselves. Only identified clients
+/// resume their per-source state when they reconnect. A client id can only be used by one
+/// connection at a time: a second connection using it while the first is open keeps its own
+/// state, and a warning is logged.
 ///
 /// # Feature Flags
 /// - `tokio`: Enables async support. Requires a Tokio runtime.
@@ -82,22 +87,628 @@ use tokio::{
 /// - [`MetricsError`](crate::error::MetricsError)
 ///
 pub struct IPCCollector {
-    socket_path: String,
+    primary: ListenerConfig,
+    listeners: Vec<ListenerConfig>,
+    sink: Arc<dyn MetricSink>,
+    self_telemetry: bool,
+    disconnect_policy: DisconnectPolicy,
+    filter: NameFilter,
+    relabel: RelabelRules,
+    gauges: GaugeMerger,
+    limits: SeriesLimits,
+    capture: CaptureConfig,
+    on_conflict: Option<ConflictHandler>,
+    max_errors: Option<NonZeroU32>,
+    max_frame_size: usize,
+    idle_timeout: Option<Duration>,
 }
 
 impl Default for IPCCollector {
     fn default() -> Self {
         Self {
-            socket_path: "metrics_collector.sock".into(),
+            primary: ListenerConfig::default(),
+            listeners: Vec::new(),
+            sink: Arc::new(GlobalRecorderSink),
+            self_telemetry: true,
+            disconnect_policy: DisconnectPolicy::Keep,
+            filter: NameFilter::default(),
+            relabel: RelabelRules::default(),
+            gauges: GaugeMerger::default(),
+            limits: SeriesLimits::default(),
+            capture: CaptureConfig::default(),
+            on_conflict: None,
+            max_errors: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            idle_timeout: None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for IPCCollector {
+    /// Creates a collector accepting clients on an already bound socket: a listening Unix domain
+    /// or TCP socket, or a Unix datagram socket.
+    ///
+    /// Any other socket, such as a stream socket that is not listening, makes
+    /// [`start_collecting`](Self::start_collecting) fail.
+    ///
+    /// The socket, TCP and permission settings are ignored, and the socket file is left in
+    /// place on shutdown, as whoever bound the socket owns it.
+    fn from(fd: OwnedFd) -> Self {
+        Self {
+            primary: ListenerConfig::from(fd),
+            ..Self::default()
         }
     }
 }
 
+#[cfg(unix)]
+impl FromRawFd for IPCCollector {
+    /// Creates a collector accepting clients on an already bound and listening socket, as with
+    /// the `From<OwnedFd>` implementation.
+    ///
+    /// # Safety
+    /// `fd` must be an open socket owned by the caller, which is closed when the collector stops.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        // SAFETY: upheld by the caller.
+        Self::from(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
 impl IPCCollector {
-    /// Sets the path for the IPC socket file.
+    /// Creates a collector accepting clients on the socket passed by systemd socket activation.
+    ///
+    /// With a `.socket` unit owning the listening socket, the collector can be restarted without
+    /// recorders ever finding the socket missing. `ListenStream=` paths and ports, and
+    /// `ListenDatagram=` paths, are supported. If several sockets are passed, the collector
+    /// listens on all of them, with the settings made on the collector applying to the first;
+    /// the others are added as [listeners](Self::listener) with the default settings.
+    ///
+    /// The sockets are marked close-on-exec, and `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`
+    /// are removed from the environment so child processes do not take them too. As with
+    /// [`std::env::remove_var`], call this before starting threads that read the environment.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let handle = IPCCollector::from_listen_fds()?.start_collecting()?;
+    /// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+    /// ```
+    ///
+    /// # Errors
+    /// Returns [`MetricsError::NotActivated`] if `LISTEN_FDS` and `LISTEN_PID` do not pass any
+    /// socket to this process, or if the sockets have already been taken.
+    #[cfg(unix)]
+    pub fn from_listen_fds() -> Result<Self, MetricsError> {
+        let mut fds = activation::listen_fds().into_iter();
+        let fd = fds.next().ok_or(MetricsError::NotActivated)?;
+        Ok(fds.fold(Self::from(fd), |collector, fd| {
+            collector.listener(ListenerConfig::from(fd))
+        }))
+    }
+
+    /// Sets the name of the IPC socket.
+    ///
+    /// The name is used in the abstract namespace where the platform supports it, and as a
+    /// socket file under `/tmp` otherwise. Use [`socket_file`](Self::socket_file) for a socket
+    /// file at a given path.
     #[must_use]
     pub fn socket(mut self, socket_path: &str) -> Self {
-        self.socket_path = socket_path.to_string();
+        self.primary.socket_path = SocketPath::Name(socket_path.to_string());
+        self
+    }
+
+    /// Listens on a socket file at `path` instead of a namespaced socket.
+    ///
+    /// Unlike namespaced sockets, socket files can be protected with
+    /// [`socket_mode`](Self::socket_mode) and [`socket_group`](Self::socket_group). Clients
+    /// connect with [`IPCRecorderBuilder::socket_file`](crate::IPCRecorderBuilder::socket_file).
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().socket_file("/run/metrics/collector.sock");
+    /// ```
+    #[must_use]
+    pub fn socket_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.primary.socket_path = SocketPath::File(path.into());
+        self
+    }
+
+    /// Listens on a TCP port instead of a local socket.
+    ///
+    /// For processes that cannot reach the filesystem or abstract namespace of the collector but
+    /// can reach a loopback port. Clients connect with
+    /// [`IPCRecorderBuilder::tcp`](crate::IPCRecorderBuilder::tcp). The listener binds to
+    /// 127.0.0.1 unless changed with [`bind_address`](Self::bind_address). Port 0 binds to any
+    /// free port, available from [`CollectorHandle::tcp_addr`].
+    ///
+    /// TCP peers have no credentials, so when a peer allowlist is configured every TCP
+    /// connection is rejected, and peer labels are not added.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().tcp(9091);
+    /// ```
+    #[must_use]
+    pub const fn tcp(mut self, port: u16) -> Self {
+        self.primary.tcp_port = Some(port);
+        self
+    }
+
+    /// Receives one event per datagram on a Unix datagram socket at `path` instead of a stream
+    /// socket.
+    ///
+    /// For processes that must never block or hold a connection open, such as signal handlers
+    /// and short-lived hooks, which send with
+    /// [`IPCRecorderBuilder::datagram`](crate::IPCRecorderBuilder::datagram). Every sender
+    /// shares a single connection that stays open for as long as the collector runs, so the
+    /// [`disconnect_policy`](Self::disconnect_policy) never applies to them, and senders have no
+    /// credentials for the peer allowlist or peer labels. If a peer allowlist is configured, the
+    /// socket is closed straight away.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().datagram("/run/metrics/collector.dgram");
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub fn datagram(mut self, path: impl Into<PathBuf>) -> Self {
+        self.primary.datagram = Some(path.into());
+        self
+    }
+
+    /// Sets the address the [TCP listener](Self::tcp) binds to, 127.0.0.1 by default.
+    ///
+    /// Binding to anything other than a loopback address exposes the collector to the network.
+    #[must_use]
+    pub const fn bind_address(mut self, address: IpAddr) -> Self {
+        self.primary.bind_address = address;
+        self
+    }
+
+    /// Sets the sink that collected metrics are dispatched to.
+    ///
+    /// Defaults to [`GlobalRecorderSink`], which forwards to the global recorder. Use
+    /// [`RecorderSink`](crate::RecorderSink) to target a specific recorder, or implement
+    /// [`MetricSink`] to feed a custom store.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_exporter_prometheus::PrometheusBuilder;
+    /// use metrics_ipc_collector::{IPCCollector, RecorderSink};
+    /// let recorder = PrometheusBuilder::new().build_recorder();
+    /// let collector = IPCCollector::default().sink(RecorderSink::new(recorder));
+    /// ```
+    #[must_use]
+    pub fn sink(mut self, sink: impl MetricSink + 'static) -> Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    /// Enables or disables the collector's own metrics, which are enabled by default.
+    ///
+    /// The collector reports its ingestion health through the same sink as the collected metrics:
+    ///
+    /// - `metrics_ipc_collector_connections_active`: client connections currently open.
+    /// - `metrics_ipc_collector_connections_accepted_total`: client connections accepted.
+    /// - `metrics_ipc_collector_connections_closed_total`: client connections closed.
+    /// - `metrics_ipc_collector_connections_rejected_total`: connections rejected by the peer allowlist.
+    /// - `metrics_ipc_collector_frames_decoded_total`: frames decoded into metric events.
+    /// - `metrics_ipc_collector_decode_errors_total`: frames that could not be decoded.
+    /// - `metrics_ipc_collector_bytes_received_total`: bytes received from clients.
+    /// - `metrics_ipc_collector_events_filtered_total`: events dropped by the
+    ///   [name filters](Self::allow_metrics).
+    /// - `metrics_ipc_collector_series_rejected_total`: metric updates dropped or collapsed by the
+    ///   [series limits](Self::max_series).
+    /// - `metrics_ipc_collector_frames_corrupt_total`: corrupt frames skipped while
+    ///   resynchronizing.
+    /// - `metrics_ipc_collector_frames_oversize_total`: frames discarded for exceeding the
+    ///   [maximum frame size](Self::max_frame_size).
+    /// - `metrics_ipc_collector_connections_dropped_total`: client connections closed for sending
+    ///   oversize frames or [too many bad frames](Self::max_consecutive_errors).
+    /// - `metrics_ipc_collector_connections_timed_out_total`: client connections closed for staying
+    ///   idle past the [idle timeout](Self::idle_timeout).
+    /// - `metrics_ipc_collector_metadata_conflicts_total`: events dropped for conflicting with how
+    ///   their metric was [first described](Self::on_metadata_conflict).
+    #[must_use]
+    pub const fn self_telemetry(mut self, enabled: bool) -> Self {
+        self.self_telemetry = enabled;
+        self
+    }
+
+    /// Sets what happens to a series once every client that reported it has disconnected.
+    ///
+    /// By default series are kept at their last value forever, so gauges set by a process that
+    /// has exited keep being exported. See [`DisconnectPolicy`] for the alternatives.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{DisconnectPolicy, IPCCollector};
+    /// let collector = IPCCollector::default().disconnect_policy(DisconnectPolicy::Zero);
+    /// ```
+    #[must_use]
+    pub const fn disconnect_policy(mut self, policy: DisconnectPolicy) -> Self {
+        self.disconnect_policy = policy;
+        self
+    }
+
+    /// Only passes on metrics whose name matches `pattern`, or any other allowed pattern.
+    ///
+    /// Filters apply to the names sent by clients, before [relabeling](Self::relabel). Once any
+    /// pattern is allowed, metrics and metadata matching none of them are dropped and counted in
+    /// `metrics_ipc_collector_events_filtered_total`. Strings are treated as globs; use
+    /// [`NamePattern::regex`] for regular expressions.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{IPCCollector, NamePattern};
+    /// let collector = IPCCollector::default()
+    ///     .allow_metrics("app_*")
+    ///     .deny_metrics(NamePattern::regex("app_debug_.*")?);
+    /// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+    /// ```
+    #[must_use]
+    pub fn allow_metrics(mut self, pattern: impl Into<NamePattern>) -> Self {
+        self.filter.allow.push(pattern.into());
+        self
+    }
+
+    /// Drops metrics whose name matches `pattern`, even if they are allowed.
+    ///
+    /// See [`allow_metrics`](Self::allow_metrics) for how filters are applied.
+    #[must_use]
+    pub fn deny_metrics(mut self, pattern: impl Into<NamePattern>) -> Self {
+        self.filter.deny.push(pattern.into());
+        self
+    }
+
+    /// Adds a relabeling rule, applied after every rule added before it.
+    ///
+    /// Rules rewrite the name and labels of every metric received, including any
+    /// [peer labels](Self::peer_labels), before it is merged or dispatched to the sink. Metadata is
+    /// renamed or dropped by the rules that only look at `__name__`. See [`RelabelRule`].
+    #[must_use]
+    pub fn relabel(mut self, rule: RelabelRule) -> Self {
+        self.relabel.push(rule);
+        self
+    }
+
+    /// Adds a list of relabeling rules, such as those loaded with [`RelabelRules::from_file`].
+    #[must_use]
+    pub fn relabel_rules(mut self, rules: RelabelRules) -> Self {
+        self.relabel.extend(rules);
+        self
+    }
+
+    /// Merges gauges whose name matches `pattern` across every client that reports them.
+    ///
+    /// Without a merge rule, the last client to set a gauge overwrites the values of all others.
+    /// With one, the collector keeps the value each connection reported and exports the merged
+    /// value instead, e.g. the sum of the queue depths of every worker. Strings are treated as
+    /// globs; use [`NamePattern::regex`] for regular expressions. When several patterns match a
+    /// gauge, the one added first is used.
+    ///
+    /// A connection's values are dropped from the merged gauges as soon as it disconnects. Values
+    /// of a client that [identified itself](crate::IPCRecorder::identify) follow the
+    /// [`DisconnectPolicy`] instead: they are kept with [`Keep`](DisconnectPolicy::Keep), so the
+    /// client can pick up where it left off when it reconnects, and with
+    /// [`RemoveAfter`](DisconnectPolicy::RemoveAfter) they are dropped once the grace period ends.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{DisconnectPolicy, GaugeMerge, IPCCollector, NamePattern};
+    /// let collector = IPCCollector::default()
+    ///     .gauge_merge("queue_depth", GaugeMerge::Sum)
+    ///     .gauge_merge("worker_*", GaugeMerge::PerSource)
+    ///     .gauge_merge(NamePattern::regex("memory_.*_bytes")?, GaugeMerge::Max)
+    ///     .disconnect_policy(DisconnectPolicy::Remove);
+    /// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+    /// ```
+    #[must_use]
+    pub fn gauge_merge(mut self, pattern: impl Into<NamePattern>, merge: GaugeMerge) -> Self {
+        self.gauges.add_rule(pattern.into(), merge);
+        self
+    }
+
+    /// Limits the number of distinct series the collector forwards to its sink.
+    ///
+    /// Once `max` series have been seen, updates to any new series are handled according to
+    /// [`cardinality_overflow`](Self::cardinality_overflow) and counted in
+    /// `metrics_ipc_collector_series_rejected_total`. Series that were already admitted keep
+    /// being updated. Series removed by the [`DisconnectPolicy`] free their slot.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default()
+    ///     .max_series(10_000)
+    ///     .max_series_per_metric(500);
+    /// ```
+    #[must_use]
+    pub const fn max_series(mut self, max: usize) -> Self {
+        self.limits.max_series = Some(max);
+        self
+    }
+
+    /// Limits the number of distinct series of any single metric name.
+    ///
+    /// This stops one metric with an unbounded label, such as a request id, from using up the
+    /// whole [`max_series`](Self::max_series) budget. Overflowing series are handled the same way.
+    #[must_use]
+    pub const fn max_series_per_metric(mut self, max: usize) -> Self {
+        self.limits.max_series_per_metric = Some(max);
+        self
+    }
+
+    /// Sets what happens to new series once a series limit has been reached.
+    ///
+    /// Defaults to [`CardinalityOverflow::Drop`].
+    #[must_use]
+    pub const fn cardinality_overflow(mut self, overflow: CardinalityOverflow) -> Self {
+        self.limits.overflow = overflow;
+        self
+    }
+
+    /// Calls `handler` for every event that conflicts with how its metric was first described.
+    ///
+    /// The first description of a metric name fixes its kind and unit. Descriptions with another
+    /// kind or unit, and updates for another kind of metric, are dropped so the exporter stays
+    /// consistent, counted in `metrics_ipc_collector_metadata_conflicts_total` and reported to
+    /// the handler. Conflicts are detected whether or not a handler is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().on_metadata_conflict(|conflict| {
+    ///     eprintln!("connection {}: {conflict}", conflict.connection_id);
+    /// });
+    /// ```
+    #[must_use]
+    pub fn on_metadata_conflict(
+        mut self,
+        handler: impl Fn(&MetadataConflict) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_conflict = Some(Arc::new(handler));
+        self
+    }
+
+    /// Closes connections once they send `max_errors` corrupt or undecodable frames in a row.
+    ///
+    /// After a corrupt frame the collector skips ahead to the start of the next frame, so a
+    /// connection normally recovers by itself, but a client that only ever sends garbage is
+    /// better disconnected. Closed connections are counted in
+    /// `metrics_ipc_collector_connections_dropped_total` and handled like any other
+    /// disconnect. By default, and with `max_errors` set to 0, connections are never closed for
+    /// bad frames.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().max_consecutive_errors(16);
+    /// ```
+    #[must_use]
+    pub const fn max_consecutive_errors(mut self, max_errors: u32) -> Self {
+        self.max_errors = NonZeroU32::new(max_errors);
+        self
+    }
+
+    /// Sets the largest frame payload accepted, in bytes. Defaults to 1 MiB.
+    ///
+    /// A client sending a larger frame, or that many bytes without ending a newline terminated
+    /// frame, has its connection closed before the frame is buffered. The frame is counted in
+    /// `metrics_ipc_collector_frames_oversize_total` and the connection in
+    /// `metrics_ipc_collector_connections_dropped_total`. Oversize datagrams are discarded, but
+    /// the socket stays open for other senders.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().max_frame_size(64 * 1024);
+    /// ```
+    #[must_use]
+    pub const fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Closes connections that receive nothing for longer than `timeout`.
+    ///
+    /// A client that hangs or is stopped keeps its connection open, and without the `tokio`
+    /// feature a thread with it, until it exits. Idle connections are counted in
+    /// `metrics_ipc_collector_connections_timed_out_total` and closed like any other disconnect,
+    /// so the [disconnect policy](Self::disconnect_policy) applies to their series. Clients that
+    /// may have nothing to record for longer should send
+    /// [heartbeats](crate::IPCRecorderBuilder::heartbeat) more often than this. Datagram sockets
+    /// are never timed out. By default idle connections are kept open.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// use std::time::Duration;
+    /// let collector = IPCCollector::default().idle_timeout(Duration::from_secs(60));
+    /// ```
+    #[must_use]
+    pub const fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Appends every frame received to a capture file, for replay with
+    /// [`CollectorHandle::replay`].
+    ///
+    /// Frame payloads are recorded exactly as they were received, along with the id of the
+    /// connection they were received on, the labels the collector added to it and the time they
+    /// arrived, including payloads that fail to decode. Data skipped as corrupt is not recorded.
+    /// The file is appended to if it already exists, unless it was written by an older version,
+    /// in which case it is rotated away. Files are rotated according to
+    /// [`capture_rotation`](Self::capture_rotation).
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default()
+    ///     .capture("/var/lib/metrics/frames.cap")
+    ///     .capture_rotation(16 * 1024 * 1024, 3);
+    /// ```
+    #[must_use]
+    pub fn capture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture.path = Some(path.into());
+        self
+    }
+
+    /// Rotates the [capture file](Self::capture) once it would grow past `max_bytes`, keeping
+    /// `keep` rotated files named `<path>.1` (newest) to `<path>.<keep>` (oldest).
+    ///
+    /// Defaults to 64 MiB and 5 files.
+    #[must_use]
+    pub const fn capture_rotation(mut self, max_bytes: u64, keep: usize) -> Self {
+        self.capture.max_bytes = max_bytes;
+        self.capture.keep = keep;
+        self
+    }
+
+    /// Sets the file mode applied to the socket file when it is created, e.g. `0o660`.
+    ///
+    /// The mode is applied before the socket is bound, so the process umask never leaves it
+    /// world-writable. Only connections permitted by the write bits can connect.
+    /// Ignored for namespaced sockets, which have no file.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default()
+    ///     .socket_file("/run/metrics/collector.sock")
+    ///     .socket_mode(0o660);
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_mode(mut self, mode: u32) -> Self {
+        self.primary.permissions.mode = Some(mode);
+        self
+    }
+
+    /// Sets the group that owns the socket file once it has been created.
+    ///
+    /// Combine with [`socket_mode`](Self::socket_mode) to allow only members of the group to connect.
+    /// Ignored for namespaced sockets, which have no file.
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_group(mut self, gid: u32) -> Self {
+        self.primary.permissions.group = Some(gid);
+        self
+    }
+
+    /// Sets the mode of the directory containing the socket file when it does not exist yet.
+    ///
+    /// The directory is created, along with any missing parents, and every directory created is
+    /// given this mode. Directories that already exist, such as `/run`, are left as they are.
+    /// Ignored for namespaced sockets, which have no file.
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_dir_mode(mut self, mode: u32) -> Self {
+        self.primary.permissions.dir_mode = Some(mode);
+        self
+    }
+
+    /// Allows processes running as `uid` to send metrics.
+    ///
+    /// Once any uid, gid or pid has been allowed, the collector reads the credentials of each
+    /// connecting peer and closes connections from peers that match none of them.
+    /// Rejected connections are logged and counted in `metrics_ipc_collector_connections_rejected_total`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().allow_uid(1000).allow_gid(100);
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.primary.policy.allowlist.uids.push(uid);
+        self
+    }
+
+    /// Allows processes whose effective group is `gid` to send metrics.
+    ///
+    /// See [`allow_uid`](Self::allow_uid) for how the allowlist is applied.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.primary.policy.allowlist.gids.push(gid);
+        self
+    }
+
+    /// Allows the process with id `pid` to send metrics.
+    ///
+    /// See [`allow_uid`](Self::allow_uid) for how the allowlist is applied.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_pid(mut self, pid: u32) -> Self {
+        self.primary.policy.allowlist.pids.push(pid);
+        self
+    }
+
+    /// Adds labels identifying the sending process to every metric received by the collector.
+    ///
+    /// The values are taken from the peer credentials of each connection, so processes that
+    /// report the same series are kept apart instead of being merged.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{IPCCollector, PeerLabel};
+    /// let collector = IPCCollector::default().peer_labels(&[PeerLabel::Pid, PeerLabel::Exe]);
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub fn peer_labels(mut self, labels: &[PeerLabel]) -> Self {
+        self.primary.policy.peer_labels = labels.to_vec();
+        self
+    }
+
+    /// Adds a fixed label to every metric received by the collector's own listener.
+    ///
+    /// Overrides a label of the same name set by the client or a [peer label](Self::peer_labels).
+    /// Mostly useful to tell apart metrics received through different
+    /// [listeners](Self::listener).
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let collector = IPCCollector::default().label("tier", "system");
+    /// ```
+    #[must_use]
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.primary
+            .policy
+            .labels
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Accepts clients on another socket, with its own permissions, allowlist and labels.
+    ///
+    /// The socket, permission, allowlist and label settings made on the collector itself only
+    /// apply to its own listener, while everything else, such as the sink, filters and limits,
+    /// is shared by every listener.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{IPCCollector, ListenerConfig};
+    /// let collector = IPCCollector::default()
+    ///     .socket_file("/run/metrics/system.sock")
+    ///     .label("tier", "system")
+    ///     .listener(
+    ///         ListenerConfig::default()
+    ///             .socket_file("/run/metrics/user.sock")
+    ///             .label("tier", "user"),
+    ///     );
+    /// ```
+    #[must_use]
+    pub fn listener(mut self, listener: ListenerConfig) -> Self {
+        self.listeners.push(listener);
         self
     }
 
@@ -109,11 +720,16 @@ impl IPCCollector {
     /// - **Async Support:** If the `tokio` feature is enabled, this function uses async tasks and requires a Tokio runtime.
     ///   Otherwise, it uses threads and blocking IO.
     ///
-    /// The metrics collected can then be exported using any of the regular metric export crates.
-    /// If the socket file already exists, it will be removed before starting the collector.
+    /// The metrics collected are dispatched to the configured [`sink`](Self::sink), by default the
+    /// global recorder, and can then be exported using any of the regular metric export crates.
+    /// If the socket file already exists, it will be removed before starting the collector,
+    /// unless the collector was created from an already bound socket.
+    /// The listener is bound before this function returns, so any configured socket permissions
+    /// are already in place. The returned [`CollectorHandle`] can be used to
+    /// [subscribe](CollectorHandle::subscribe) to the events received.
     ///
     /// # Example
-    /// ```
+    /// ```no_run
     /// use metrics_ipc_collector::IPCCollector;
     /// let collector = IPCCollector::default();
     /// if let Err(e) = collector.start_collecting() {
@@ -127,215 +743,452 @@ impl IPCCollector {
     /// # Errors
     /// This function will return an error if it fails to create the socket file or if there are issues
     /// with the IPC communication.
-    pub fn start_collecting(self) -> Result<(), MetricsError> {
-        let socket_path = self.socket_path;
-        let socket_file: PathBuf = format!("/tmp/{socket_path}").into();
-        if socket_file.exists() {
-            std::fs::remove_file(&socket_file)?;
-        }
+    pub fn start_collecting(self) -> Result<CollectorHandle, MetricsError> {
+        let bound = std::iter::once(self.primary)
+            .chain(self.listeners)
+            .map(ListenerConfig::bind)
+            .collect::<Result<Vec<_>, _>>()?;
 
-        #[cfg(not(feature = "tokio"))]
-        thread::spawn(move || {
-            if let Err(e) = run_collector(socket_path.clone()) {
-                log::error!("Metrics collector error: {e}");
-            }
-            // Clean up socket file on shutdown
-            let _ = std::fs::remove_file(&socket_file);
+        let capture = CaptureWriter::open(self.capture)?;
+        let telemetry = Telemetry::new(&self.sink, self.self_telemetry);
+        telemetry.describe();
+        let shared = Arc::new(Shared {
+            sink: self.sink,
+            telemetry,
+            series: Arc::new(SeriesTracker::new(self.disconnect_policy)),
+            filter: self.filter,
+            relabel: self.relabel,
+            gauges: self.gauges,
+            limits: Arc::new(self.limits),
+            metadata: MetadataRegistry::new(self.on_conflict),
+            max_errors: self.max_errors,
+            max_frame_size: self.max_frame_size,
+            idle_timeout: self.idle_timeout,
+            counters: CounterTotals::default(),
+            connections: AtomicU64::new(0),
+            clients: Mutex::new(HashSet::new()),
+            subscribers: Subscribers::default(),
+            capture: capture.map(Mutex::new),
         });
+        let handle = CollectorHandle {
+            shared: Arc::clone(&shared),
+            tcp_addrs: bound
+                .iter()
+                .filter_map(|bound| bound.listener.tcp_addr())
+                .collect(),
+        };
+        for bound in bound {
+            bound.spawn(&shared);
+        }
+        Ok(handle)
+    }
+}
+
+/// State shared by a running collector and every connection it accepts.
+pub struct Shared {
+    pub sink: Arc<dyn MetricSink>,
+    pub telemetry: Telemetry,
+    series: Arc<SeriesTracker>,
+    filter: NameFilter,
+    relabel: RelabelRules,
+    gauges: GaugeMerger,
+    limits: Arc<SeriesLimits>,
+    pub metadata: MetadataRegistry,
+    /// Number of bad frames in a row after which a connection is closed.
+    max_errors: Option<NonZeroU32>,
+    max_frame_size: usize,
+    idle_timeout: Option<Duration>,
+    counters: CounterTotals,
+    /// Number of connections accepted so far, used to identify each connection.
+    connections: AtomicU64,
+    /// Client ids of the open connections that identified themselves.
+    clients: Mutex<HashSet<String>>,
+    pub subscribers: Subscribers,
+    capture: Option<Mutex<CaptureWriter>>,
+}
 
-        #[cfg(feature = "tokio")]
-        task::spawn(async move {
-            if let Err(e) = run_collector(socket_path.clone()).await {
-                log::error!("Metrics collector error: {e}");
+impl Shared {
+    /// Checks a newly accepted connection against the policy of its listener, returning the state used to
+    /// process its events if it is allowed.
+    pub fn accept(
+        self: &Arc<Self>,
+        policy: &ConnectionPolicy,
+        creds: std::io::Result<PeerCreds>,
+    ) -> Option<Connection> {
+        #[cfg(unix)]
+        let mut labels = {
+            if !policy.allowlist.authorize(&creds) {
+                self.telemetry.connection_rejected();
+                return None;
             }
-            // Clean up socket file on shutdown
-            let _ = std::fs::remove_file(&socket_file);
-        });
+            creds
+                .map(|creds| peer::peer_labels(&policy.peer_labels, &creds))
+                .unwrap_or_default()
+        };
+        #[cfg(not(unix))]
+        let mut labels = {
+            let _ = creds;
+            BTreeMap::new()
+        };
+        labels.extend(policy.labels.clone());
+        self.telemetry.connection_accepted();
+        let id = self.connections.fetch_add(1, Ordering::Relaxed);
+        Some(Connection {
+            id,
+            source: Source::Connection(id),
+            labels,
+            touched: HashSet::new(),
+            errors: 0,
+            shared: Arc::clone(self),
+        })
+    }
 
-        Ok(())
+    /// Creates a connection for replaying frames captured on another connection, adding the
+    /// labels the original connection added.
+    fn replay_connection(self: &Arc<Self>, labels: BTreeMap<String, String>) -> Connection {
+        let id = self.connections.fetch_add(1, Ordering::Relaxed);
+        Connection {
+            id,
+            source: Source::Connection(id),
+            labels,
+            touched: HashSet::new(),
+            errors: 0,
+            shared: Arc::clone(self),
+        }
     }
-}
 
-// We can safely filter out any errors from the incoming stream
-#[cfg(not(feature = "tokio"))]
-fn filter_streams(conn: std::io::Result<Stream>) -> Option<Stream> {
-    conn.ok()
-}
+    /// Applies the disconnect policy to the per-source state of a closed connection.
+    fn disconnect(self: &Arc<Self>, source: Source) {
+        let client_id = match &source {
+            // A new connection is always a new source, so its counter totals and gauge values can
+            // never be resumed, whatever the disconnect policy.
+            Source::Connection(_) => {
+                self.counters.remove_source(&source);
+                self.forget_source(&source);
+                return;
+            }
+            Source::Client(client_id) => client_id,
+        };
+        self.clients.lock().unwrap().remove(client_id);
 
-#[cfg(not(feature = "tokio"))]
-fn run_collector(socket_path: String) -> Result<(), MetricsError> {
-    let socket_name = if GenericNamespaced::is_supported() {
-        socket_path.to_ns_name::<GenericNamespaced>()?
-    } else {
-        format!("/tmp/{socket_path}").to_fs_name::<GenericFilePath>()?
-    };
-
-    let listener = ListenerOptions::new().name(socket_name).create_sync()?;
-    listener.set_nonblocking(ListenerNonblockingMode::Both)?;
-
-    for stream in listener.incoming().filter_map(filter_streams) {
-        thread::spawn(move || {
-            let mut reader = BufReader::new(stream);
-            let mut buffer: Vec<u8> = Vec::new();
-
-            loop {
-                buffer.clear();
-                match reader.read_until(b'\n', &mut buffer) {
-                    Ok(0) => break,
-                    Ok(_) => match MetricEvent::try_from(&buffer) {
-                        Ok(MetricEvent::Metadata(metadata)) => handle_metadata_event(metadata),
-                        Ok(MetricEvent::Metric(metric)) => handle_metric_event(metric),
-                        Err(e) => log::trace!("{e}"),
-                    },
-                    // If we encounter an error reading from the stream, we just skip it
-                    Err(_) => {}
-                }
+        match self.series.policy() {
+            DisconnectPolicy::Keep => {}
+            DisconnectPolicy::Remove | DisconnectPolicy::Zero => self.forget_source(&source),
+            DisconnectPolicy::RemoveAfter(grace) => {
+                let shared = Arc::clone(self);
+                expiry::after(grace, move || shared.forget_source(&source));
             }
-        });
+        }
     }
-    Ok(())
-}
 
-#[cfg(feature = "tokio")]
-async fn run_collector(socket_path: String) -> Result<(), MetricsError> {
-    let socket_name = if GenericNamespaced::is_supported() {
-        socket_path.to_ns_name::<GenericNamespaced>()?
-    } else {
-        format!("/tmp/{socket_path}").to_fs_name::<GenericFilePath>()?
-    };
-
-    let listener = ListenerOptions::new().name(socket_name).create_tokio()?;
-
-    loop {
-        if let Ok(stream) = listener.accept().await {
-            task::spawn(async move {
-                let mut reader = BufReader::new(stream);
-                let mut buffer: Vec<u8> = Vec::new();
-
-                loop {
-                    buffer.clear();
-                    match reader.read_until(b'\n', &mut buffer).await {
-                        Ok(0) => break,
-                        Ok(_) => match MetricEvent::try_from(&buffer) {
-                            Ok(MetricEvent::Metadata(metadata)) => {
-                                handle_metadata_event(metadata);
-                            }
-                            Ok(MetricEvent::Metric(metric)) => handle_metric_event(metric),
-                            Err(e) => log::trace!("{e}"),
-                        },
-                        // If we encounter an error reading from the stream, we just skip it
-                        Err(_) => {}
-                    }
-                }
-            });
+    /// Records that a connection identified itself as `client_id`, returning `false` if another
+    /// open connection already uses it.
+    fn identify(&self, client_id: &str) -> bool {
+        self.clients.lock().unwrap().insert(client_id.to_string())
+    }
+
+    /// Drops the gauge values of a disconnected source, unless it has reconnected since.
+    fn forget_source(&self, source: &Source) {
+        if let Source::Client(client_id) = source
+            && self.clients.lock().unwrap().contains(client_id)
+        {
+            return;
+        }
+        for metric in self.gauges.remove_source(source) {
+            self.sink.handle_metric(metric);
         }
     }
 }
 
-fn handle_metric_event(metric: MetricData) {
-    match metric.operation {
-        MetricOperation::IncrementCounter(value) => {
-            if metric.labels.is_empty() {
-                metrics::counter!(metric.name).increment(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::counter!(metric.name, &labels).increment(value);
+/// State for a single accepted connection.
+pub struct Connection {
+    /// Numbers connections in the order they were accepted.
+    id: u64,
+    /// Identifies the sending process in per-source state.
+    source: Source,
+    /// Labels derived from the peer, added to every metric received on the connection.
+    labels: BTreeMap<String, String>,
+    /// Series reported on this connection, released when it closes.
+    touched: HashSet<SeriesKey>,
+    /// Number of corrupt or undecodable frames received since the last good one.
+    errors: u32,
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    /// Captures the payload of a frame read from the connection's stream, if enabled, then
+    /// handles it.
+    pub fn receive(&mut self, buffer: &Vec<u8>) {
+        if let Some(capture) = &self.shared.capture {
+            let written =
+                capture
+                    .lock()
+                    .unwrap()
+                    .write(SystemTime::now(), self.id, &self.labels, buffer);
+            if let Err(e) = written {
+                log::warn!("Failed to capture frame: {e}");
             }
         }
-        MetricOperation::SetCounter(value) => {
-            if metric.labels.is_empty() {
-                metrics::counter!(metric.name).absolute(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::counter!(metric.name, &labels).absolute(value);
+        self.handle_frame(buffer);
+    }
+
+    fn handle_frame(&mut self, buffer: &Vec<u8>) {
+        let Shared {
+            sink,
+            telemetry,
+            series,
+            filter,
+            relabel,
+            gauges,
+            limits,
+            metadata,
+            counters,
+            subscribers,
+            ..
+        } = &*self.shared;
+        telemetry.bytes_received(buffer.len());
+
+        match MetricEvent::try_from(buffer) {
+            Ok(event) => {
+                self.errors = 0;
+                telemetry.frame_decoded();
+                if subscribers.is_active() {
+                    subscribers.publish(&ReceivedEvent {
+                        connection_id: self.id,
+                        received_at: SystemTime::now(),
+                        event: event.clone(),
+                    });
+                }
+                let name = match &event {
+                    MetricEvent::Metadata(metadata) => Some(&metadata.name),
+                    MetricEvent::Metric(metric) => Some(&metric.name),
+                    MetricEvent::Hello(_) | MetricEvent::Heartbeat => None,
+                };
+                if name.is_some_and(|name| !filter.permits(name)) {
+                    telemetry.event_filtered();
+                    return;
+                }
+                match event {
+                    MetricEvent::Metadata(described) => {
+                        let Some(described) = relabel.relabel_metadata(described) else {
+                            return;
+                        };
+                        match metadata.declare(&described) {
+                            Ok(merged) => sink.handle_metadata(merged),
+                            Err(declared) => {
+                                self.conflict(declared, MetricEvent::Metadata(described));
+                            }
+                        }
+                    }
+                    MetricEvent::Hello(hello) => self.identify(hello.client_id),
+                    MetricEvent::Heartbeat => {}
+                    MetricEvent::Metric(mut metric) => {
+                        metric.labels.extend(self.labels.clone());
+                        let Some(metric) = relabel.relabel_metric(metric) else {
+                            return;
+                        };
+                        if let Some(declared) = metadata.check(&metric) {
+                            self.conflict(declared, MetricEvent::Metric(metric));
+                            return;
+                        }
+                        let metric = gauges.label_source(&self.source, metric);
+                        let (metric, rejected) = limits.apply(metric);
+                        if rejected {
+                            telemetry.series_rejected();
+                        }
+                        let Some(metric) = metric else {
+                            return;
+                        };
+                        // Only once admitted, so rejected series leave no per-source state behind.
+                        let metric = gauges.apply(&self.source, metric);
+                        let metric = counters.apply(&self.source, metric);
+                        if series.is_enabled() {
+                            let key = SeriesKey::from(&metric);
+                            if !self.touched.contains(&key) {
+                                self.touched.insert(key.clone());
+                                series.acquire(key);
+                            }
+                        }
+                        sink.handle_metric(metric);
+                    }
+                }
             }
-        }
-        MetricOperation::IncrementGauge(value) => {
-            if metric.labels.is_empty() {
-                metrics::gauge!(metric.name).increment(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::gauge!(metric.name, &labels).increment(value);
+            Err(e) => {
+                self.errors += 1;
+                telemetry.decode_error();
+                log::trace!("{e}");
             }
         }
-        MetricOperation::DecrementGauge(value) => {
-            if metric.labels.is_empty() {
-                metrics::gauge!(metric.name).decrement(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::gauge!(metric.name, &labels).decrement(value);
-            }
+    }
+
+    /// Counts data skipped while resynchronizing after a corrupt frame.
+    pub fn corrupt(&mut self, skipped: usize) {
+        self.errors += 1;
+        self.shared.telemetry.frame_corrupt();
+        log::trace!(
+            "Skipped {skipped} bytes of corrupt data on connection {}",
+            self.id
+        );
+    }
+
+    /// Returns `true` once the connection has sent more bad frames in a row than allowed.
+    pub fn is_failing(&self) -> bool {
+        self.shared
+            .max_errors
+            .is_some_and(|max_errors| self.errors >= max_errors.get())
+    }
+
+    /// Closes a connection that is failing, counting it as dropped.
+    pub fn drop_failing(self) {
+        log::warn!(
+            "Closing connection {} after {} bad frames in a row",
+            self.id,
+            self.errors
+        );
+        self.dropped();
+    }
+
+    /// Returns the largest frame payload accepted on the connection.
+    pub fn max_frame_size(&self) -> usize {
+        self.shared.max_frame_size
+    }
+
+    /// Counts a frame of `size` bytes discarded for exceeding the maximum frame size.
+    pub fn oversize(&self, size: usize) {
+        self.shared.telemetry.frame_oversize();
+        log::warn!(
+            "Discarded a frame of {size} bytes on connection {}, over the maximum of {} bytes",
+            self.id,
+            self.shared.max_frame_size
+        );
+    }
+
+    /// Returns how long the connection may stay idle before it is closed.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.shared.idle_timeout
+    }
+
+    /// Closes a connection that has been idle for longer than the idle timeout.
+    pub fn timed_out(self) {
+        log::info!("Closing connection {} after it went idle", self.id);
+        self.shared.telemetry.connection_timed_out();
+        self.close();
+    }
+
+    /// Closes the connection, counting it as dropped.
+    pub fn dropped(self) {
+        self.shared.telemetry.connection_dropped();
+        self.close();
+    }
+
+    pub fn close(self) {
+        self.shared.telemetry.connection_closed();
+        self.release();
+    }
+
+    /// Counts and reports an event dropped for conflicting with how its metric was described.
+    fn conflict(&self, declared: MetricMetadata, conflicting: MetricEvent) {
+        self.shared.telemetry.metadata_conflict();
+        self.shared.metadata.report(&MetadataConflict {
+            connection_id: self.id,
+            declared,
+            conflicting,
+        });
+    }
+
+    /// Releases everything the connection holds, applying the disconnect policy.
+    fn release(self) {
+        if let Some(capture) = &self.shared.capture {
+            capture.lock().unwrap().close(self.id);
         }
-        MetricOperation::SetGauge(value) => {
-            if metric.labels.is_empty() {
-                metrics::gauge!(metric.name).set(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::gauge!(metric.name, &labels).set(value);
-            }
+        self.shared
+            .series
+            .release(&self.shared.sink, &self.shared.limits, self.touched);
+        self.shared.disconnect(self.source);
+    }
+
+    /// Switches the connection to the client id it identified itself with.
+    fn identify(&mut self, client_id: String) {
+        if let Source::Client(current) = &self.source {
+            log::debug!("Client {current} identified itself again as {client_id}, ignoring");
+            return;
         }
-        MetricOperation::RecordHistogram(value) => {
-            if metric.labels.is_empty() {
-                metrics::histogram!(metric.name).record(value);
-            } else {
-                let labels: Vec<_> = metric
-                    .labels
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                metrics::histogram!(metric.name, &labels).record(value);
-            }
+        if !self.shared.identify(&client_id) {
+            log::warn!(
+                "Connection {} identified itself as {client_id}, which another open connection \
+                 already uses; keeping its state separate",
+                self.id
+            );
+            return;
         }
+        self.shared.counters.remove_source(&self.source);
+        self.source = Source::Client(client_id);
     }
 }
 
-fn handle_metadata_event(metadata: MetricMetadata) {
-    let unit = metadata
-        .unit
-        .clone()
-        .and_then(|ref u| metrics::Unit::from_string(u));
-
-    match metadata.kind {
-        MetricKind::Counter => {
-            if let Some(unit) = unit {
-                metrics::describe_counter!(metadata.name, unit, metadata.description);
-            } else {
-                metrics::describe_counter!(metadata.name, metadata.description);
-            }
+/// Feeds captured frames back through a collector, on one connection per captured connection.
+pub struct Replay {
+    shared: Arc<Shared>,
+    connections: HashMap<u64, Connection>,
+    /// Multiplier applied to the pace frames were originally received at.
+    speed: f64,
+    /// When the first frame was originally received, and when it was replayed.
+    started: Option<(SystemTime, Instant)>,
+}
+
+impl Replay {
+    /// Creates a replay at `speed` times the original pace.
+    ///
+    /// # Errors
+    /// Returns an error if `speed` is not a positive number.
+    pub fn new(shared: &Arc<Shared>, speed: f64) -> Result<Self, MetricsError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(MetricsError::ReplaySpeed(speed));
         }
-        MetricKind::Gauge => {
-            if let Some(unit) = unit {
-                metrics::describe_gauge!(metadata.name, unit, metadata.description);
-            } else {
-                metrics::describe_gauge!(metadata.name, metadata.description);
-            }
+        Ok(Self {
+            shared: Arc::clone(shared),
+            connections: HashMap::new(),
+            speed,
+            started: None,
+        })
+    }
+
+    /// Opens a capture file, returning its frames.
+    pub fn frames(path: &Path) -> Result<CaptureReader, MetricsError> {
+        Ok(CaptureReader::open(path)?)
+    }
+
+    /// Returns how long to wait before replaying `frame` to keep to the replay speed.
+    pub fn delay(&mut self, frame: &CapturedFrame) -> Option<Duration> {
+        if self.speed.is_infinite() {
+            return None;
         }
-        MetricKind::Histogram => {
-            if let Some(unit) = unit {
-                metrics::describe_histogram!(metadata.name, unit, metadata.description);
-            } else {
-                metrics::describe_histogram!(metadata.name, metadata.description);
+        let (first, replayed) = *self
+            .started
+            .get_or_insert_with(|| (frame.received_at, Instant::now()));
+        let offset = frame.received_at.duration_since(first).ok()?;
+        // Slow enough speeds put frames further out than a `Duration` reaches.
+        Duration::try_from_secs_f64(offset.as_secs_f64() / self.speed)
+            .unwrap_or(Duration::MAX)
+            .checked_sub(replayed.elapsed())
+    }
+
+    pub fn feed(&mut self, frame: &CapturedFrame) {
+        let connection = match self.connections.entry(frame.connection_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let labels = frame.labels.clone().unwrap_or_default();
+                entry.insert(self.shared.replay_connection(labels))
             }
+        };
+        connection.handle_frame(&frame.frame);
+    }
+}
+
+impl Drop for Replay {
+    fn drop(&mut self) {
+        for (_, connection) in self.connections.drain() {
+            connection.release();
         }
     }
 }
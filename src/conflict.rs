@@ -0,0 +1,133 @@
+//! Detection of metrics used inconsistently with how they were first described, and the cache of
+//! descriptions it keeps.
+//!
+//! The first description of a metric name fixes its kind and unit. Later descriptions with a
+//! different kind or unit, and updates whose operation belongs to another kind, conflict with it.
+//! Compatible descriptions update the cached one, so it can be described to the sink again.
+
+use crate::events::{MetricData, MetricEvent, MetricMetadata};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// A callback notified of every [`MetadataConflict`].
+pub type ConflictHandler = Arc<dyn Fn(&MetadataConflict) + Send + Sync>;
+
+/// An event that conflicts with how its metric was first described, received by the collector.
+///
+/// The conflicting event is dropped, so the exporter only ever sees the metric as first
+/// described. Reported to the handler set with
+/// [`IPCCollector::on_metadata_conflict`](crate::IPCCollector::on_metadata_conflict).
+#[derive(Debug, Clone)]
+pub struct MetadataConflict {
+    /// The connection the conflicting event was received on.
+    pub connection_id: u64,
+    /// The metadata the metric was first described with.
+    pub declared: MetricMetadata,
+    /// The event that conflicts with it, either metadata with another kind or unit, or a metric
+    /// update for another kind.
+    pub conflicting: MetricEvent,
+}
+
+impl fmt::Display for MetadataConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let declared = &self.declared;
+        write!(
+            f,
+            "metric `{}` first described as {:?} ({}) ",
+            declared.name,
+            declared.kind,
+            declared.unit.as_deref().unwrap_or("no unit")
+        )?;
+        match &self.conflicting {
+            MetricEvent::Metadata(metadata) => write!(
+                f,
+                "but described again as {:?} ({})",
+                metadata.kind,
+                metadata.unit.as_deref().unwrap_or("no unit")
+            ),
+            MetricEvent::Metric(metric) => {
+                write!(f, "but recorded as {:?}", metric.operation.kind())
+            }
+            MetricEvent::Hello(_) | MetricEvent::Heartbeat => {
+                write!(f, "but received an unrelated event")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MetadataConflict {}
+
+/// The current description of every metric name, and who to tell about conflicts with them.
+#[derive(Default)]
+pub struct MetadataRegistry {
+    declared: Mutex<HashMap<String, MetricMetadata>>,
+    handler: Option<ConflictHandler>,
+}
+
+impl MetadataRegistry {
+    pub fn new(handler: Option<ConflictHandler>) -> Self {
+        Self {
+            declared: Mutex::default(),
+            handler,
+        }
+    }
+
+    /// Records the description of a metric, returning the description to forward to the sink,
+    /// or the cached description as the error if this one conflicts with it.
+    ///
+    /// A description without a unit never conflicts on its unit. A compatible description
+    /// replaces the cached description text, and supplies the unit if none was known yet. The
+    /// description returned keeps the cached unit, so the sink never sees the unit dropped.
+    ///
+    /// # Errors
+    /// Returns the cached description if this one has another kind or unit.
+    pub fn declare(&self, metadata: &MetricMetadata) -> Result<MetricMetadata, MetricMetadata> {
+        let mut declared = self.declared.lock().unwrap();
+        let cached = match declared.entry(metadata.name.clone()) {
+            Entry::Vacant(entry) => return Ok(entry.insert(metadata.clone()).clone()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        };
+        let units_differ = matches!(
+            (&cached.unit, &metadata.unit),
+            (Some(cached), Some(unit)) if cached != unit
+        );
+        if cached.kind != metadata.kind || units_differ {
+            return Err(cached.clone());
+        }
+        cached.description.clone_from(&metadata.description);
+        if cached.unit.is_none() {
+            cached.unit.clone_from(&metadata.unit);
+        }
+        let merged = cached.clone();
+        drop(declared);
+        Ok(merged)
+    }
+
+    /// Returns the description of a metric if the update is for another kind of metric.
+    pub fn check(&self, metric: &MetricData) -> Option<MetricMetadata> {
+        self.declared
+            .lock()
+            .unwrap()
+            .get(&metric.name)
+            .filter(|first| first.kind != metric.operation.kind())
+            .cloned()
+    }
+
+    /// Returns the cached description of every metric, sorted by name.
+    pub fn descriptions(&self) -> Vec<MetricMetadata> {
+        let mut descriptions: Vec<_> = self.declared.lock().unwrap().values().cloned().collect();
+        descriptions.sort_by(|a, b| a.name.cmp(&b.name));
+        descriptions
+    }
+
+    /// Logs a conflict and passes it to the handler, if there is one.
+    pub fn report(&self, conflict: &MetadataConflict) {
+        log::debug!("Dropping conflicting event: {conflict}");
+        if let Some(handler) = &self.handler {
+            handler(conflict);
+        }
+    }
+}
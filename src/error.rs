@@ -23,4 +23,16 @@ pub enum MetricsError {
     /// Failed to deserialize metric event.
     #[error("failed to deserialize event: {0}")]
     Deserialization(#[from] rmp_serde::decode::Error),
+    /// A relabeling rule has an invalid regular expression.
+    #[error("invalid relabel regex: {0}")]
+    Regex(#[from] regex::Error),
+    /// Failed to parse a configuration file.
+    #[error("failed to parse config: {0}")]
+    Config(#[from] toml::de::Error),
+    /// No listening socket was passed by the service manager.
+    #[error("no listening socket was passed by the service manager")]
+    NotActivated,
+    /// A capture was replayed at a speed that is not a positive number.
+    #[error("invalid replay speed {0}, it must be a positive number")]
+    ReplaySpeed(f64),
 }
@@ -6,7 +6,7 @@ use std::collections::BTreeMap;
 ///
 /// Used to distinguish between counters, gauges, and histograms.
 ///
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum MetricKind {
     Counter,
     Gauge,
@@ -22,7 +22,8 @@ pub struct MetricMetadata {
     pub name: String,
     pub kind: MetricKind,
     pub description: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // Skipped units are the trailing element of the encoded array, so they must default on decode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub unit: Option<String>,
 }
 
@@ -54,6 +55,30 @@ pub enum MetricOperation {
     RecordHistogram(f64),
 }
 
+impl MetricOperation {
+    /// Returns the kind of metric this operation applies to.
+    #[must_use]
+    pub const fn kind(&self) -> MetricKind {
+        match self {
+            Self::IncrementCounter(_) | Self::SetCounter(_) => MetricKind::Counter,
+            Self::IncrementGauge(_) | Self::DecrementGauge(_) | Self::SetGauge(_) => {
+                MetricKind::Gauge
+            }
+            Self::RecordHistogram(_) => MetricKind::Histogram,
+        }
+    }
+}
+
+/// Identifies a client to the collector.
+///
+/// Sent once when a client connects, so the collector can key its per-source state by a name that
+/// survives reconnects rather than by connection.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub client_id: String,
+}
+
 /// An event sent over IPC, representing either metric metadata or metric data.
 ///
 /// Used for communication between processes and the collector.
@@ -65,6 +90,10 @@ pub enum MetricEvent {
     Metadata(MetricMetadata),
     /// Data for a single metric event (name, labels, operation).
     Metric(MetricData),
+    /// Identification of the client sending the events that follow.
+    Hello(ClientHello),
+    /// Sent periodically by a client to show it is still alive while it has nothing to record.
+    Heartbeat,
 }
 
 impl TryFrom<&Vec<u8>> for MetricEvent {
@@ -82,3 +111,38 @@ impl TryFrom<MetricEvent> for Vec<u8> {
         rmp_serde::to_vec(&event).map_err(MetricsError::from)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(metadata: MetricMetadata) -> MetricMetadata {
+        let encoded = Vec::try_from(MetricEvent::Metadata(metadata)).unwrap();
+        match MetricEvent::try_from(&encoded).unwrap() {
+            MetricEvent::Metadata(metadata) => metadata,
+            event => panic!("expected metadata, got {event:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trips_metadata_with_and_without_a_unit() {
+        let metadata = round_trip(MetricMetadata {
+            name: "queue_depth".to_string(),
+            kind: MetricKind::Gauge,
+            description: "Jobs waiting".to_string(),
+            unit: None,
+        });
+        assert_eq!(metadata.name, "queue_depth");
+        assert_eq!(metadata.kind, MetricKind::Gauge);
+        assert_eq!(metadata.description, "Jobs waiting");
+        assert_eq!(metadata.unit, None);
+
+        let metadata = round_trip(MetricMetadata {
+            name: "request_duration".to_string(),
+            kind: MetricKind::Histogram,
+            description: String::new(),
+            unit: Some("seconds".to_string()),
+        });
+        assert_eq!(metadata.unit.as_deref(), Some("seconds"));
+    }
+}
@@ -0,0 +1,400 @@
+//! Expiry of series reported by clients that have disconnected.
+//!
+//! The collector counts how many open connections have touched each series. When the last one
+//! closes, the series is orphaned and the configured [`DisconnectPolicy`] decides what happens to
+//! it in the sink.
+
+use crate::{
+    cardinality::SeriesLimits,
+    events::{MetricData, MetricKind, MetricOperation},
+    sink::MetricSink,
+};
+#[cfg(not(feature = "tokio"))]
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{Condvar, OnceLock},
+    thread,
+    time::Instant,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// What the collector does with a series once every client that reported it has disconnected.
+///
+/// Removal relies on [`MetricSink::remove_metric`]. The recorder sinks cannot forget a series, so
+/// they set removed gauges to zero and leave counters and histograms at their last value.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{DisconnectPolicy, IPCCollector};
+/// use std::time::Duration;
+/// let collector = IPCCollector::default()
+///     .disconnect_policy(DisconnectPolicy::RemoveAfter(Duration::from_secs(60)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisconnectPolicy {
+    /// Leave the series at its last value. This is the default.
+    #[default]
+    Keep,
+    /// Remove the series from the sink as soon as the last client disconnects.
+    Remove,
+    /// Set gauges to zero as soon as the last client disconnects.
+    ///
+    /// Counters and histograms are left in place.
+    Zero,
+    /// Keep the series for a grace period, then remove it unless a client has reported it again.
+    RemoveAfter(Duration),
+}
+
+/// Identifies a single series in the sink.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeriesKey {
+    pub kind: MetricKind,
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl From<&MetricData> for SeriesKey {
+    fn from(metric: &MetricData) -> Self {
+        Self {
+            kind: metric.operation.kind(),
+            name: metric.name.clone(),
+            labels: metric.labels.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Ownership {
+    /// Number of open connections that have touched the series.
+    connections: usize,
+    /// Incremented each time the series is orphaned, so a pending expiry can tell whether the
+    /// series was reported again during its grace period.
+    generation: u64,
+}
+
+/// Tracks which series are held by open connections and expires orphaned ones.
+#[derive(Debug)]
+pub struct SeriesTracker {
+    policy: DisconnectPolicy,
+    series: Mutex<HashMap<SeriesKey, Ownership>>,
+}
+
+impl SeriesTracker {
+    pub fn new(policy: DisconnectPolicy) -> Self {
+        Self {
+            policy,
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub const fn policy(&self) -> DisconnectPolicy {
+        self.policy
+    }
+
+    /// Returns `false` if series are kept forever, in which case nothing needs tracking.
+    pub fn is_enabled(&self) -> bool {
+        self.policy != DisconnectPolicy::Keep
+    }
+
+    /// Records that a connection has touched `key` for the first time.
+    pub fn acquire(&self, key: SeriesKey) {
+        let mut series = self.series.lock().unwrap();
+        series.entry(key).or_default().connections += 1;
+    }
+
+    /// Releases every series touched by a closed connection, applying the disconnect policy to
+    /// those no longer held by any connection.
+    ///
+    /// Removed series no longer count towards the series `limits`.
+    pub fn release(
+        self: &Arc<Self>,
+        sink: &Arc<dyn MetricSink>,
+        limits: &Arc<SeriesLimits>,
+        touched: HashSet<SeriesKey>,
+    ) {
+        let orphaned: Vec<(SeriesKey, u64)> = {
+            let mut series = self.series.lock().unwrap();
+            touched
+                .into_iter()
+                .filter_map(|key| {
+                    let ownership = series.get_mut(&key)?;
+                    ownership.connections = ownership.connections.saturating_sub(1);
+                    if ownership.connections > 0 {
+                        return None;
+                    }
+                    ownership.generation += 1;
+                    Some((key, ownership.generation))
+                })
+                .collect()
+        };
+
+        match self.policy {
+            DisconnectPolicy::Keep => {}
+            DisconnectPolicy::Remove => self.expire(sink, limits, orphaned),
+            DisconnectPolicy::Zero => {
+                for key in self.forget(orphaned) {
+                    if key.kind == MetricKind::Gauge {
+                        sink.handle_metric(MetricData {
+                            name: key.name,
+                            labels: key.labels,
+                            operation: MetricOperation::SetGauge(0.0),
+                        });
+                    }
+                }
+            }
+            DisconnectPolicy::RemoveAfter(grace) => {
+                let tracker = Arc::clone(self);
+                let sink = Arc::clone(sink);
+                let limits = Arc::clone(limits);
+                after(grace, move || tracker.expire(&sink, &limits, orphaned));
+            }
+        }
+    }
+
+    /// Removes the orphaned series that have not been touched again since they were orphaned.
+    fn expire(
+        &self,
+        sink: &Arc<dyn MetricSink>,
+        limits: &SeriesLimits,
+        orphaned: Vec<(SeriesKey, u64)>,
+    ) {
+        for key in self.forget(orphaned) {
+            sink.remove_metric(key.kind, &key.name, &key.labels);
+            limits.remove(&key);
+        }
+    }
+
+    /// Stops tracking the orphaned series that are still unowned, returning them.
+    fn forget(&self, orphaned: Vec<(SeriesKey, u64)>) -> Vec<SeriesKey> {
+        let mut series = self.series.lock().unwrap();
+        orphaned
+            .into_iter()
+            .filter(|(key, generation)| {
+                let unowned = series
+                    .get(key)
+                    .is_some_and(|o| o.connections == 0 && o.generation == *generation);
+                if unowned {
+                    series.remove(key);
+                }
+                unowned
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+/// Runs `f` once `delay` has elapsed, without blocking the caller.
+///
+/// Every delayed task runs on the same thread, so a burst of disconnects does not leave a
+/// sleeping thread behind for each of them.
+#[cfg(not(feature = "tokio"))]
+pub fn after(delay: Duration, f: impl FnOnce() + Send + 'static) {
+    static TIMER: OnceLock<Arc<Timer>> = OnceLock::new();
+    let timer = TIMER.get_or_init(|| {
+        let timer = Arc::new(Timer::default());
+        let running = Arc::clone(&timer);
+        thread::Builder::new()
+            .name("metrics-ipc-expiry".into())
+            .spawn(move || running.run())
+            .expect("failed to spawn the expiry thread");
+        timer
+    });
+    timer.schedule(Instant::now() + delay, Box::new(f));
+}
+
+/// A task waiting in the [`Timer`] queue, ordered by when it is due.
+#[cfg(not(feature = "tokio"))]
+struct Scheduled {
+    at: Instant,
+    /// Keeps tasks due at the same instant in the order they were scheduled.
+    sequence: u64,
+    task: Box<dyn FnOnce() + Send>,
+}
+
+#[cfg(not(feature = "tokio"))]
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl Eq for Scheduled {}
+
+#[cfg(not(feature = "tokio"))]
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+#[derive(Default)]
+struct Queue {
+    tasks: BinaryHeap<Reverse<Scheduled>>,
+    scheduled: u64,
+}
+
+/// Runs delayed tasks on a single thread, in the order they are due.
+#[cfg(not(feature = "tokio"))]
+#[derive(Default)]
+struct Timer {
+    queue: Mutex<Queue>,
+    /// Signalled when a task is scheduled, as it may be due before the one being waited for.
+    scheduled: Condvar,
+}
+
+#[cfg(not(feature = "tokio"))]
+impl Timer {
+    fn schedule(&self, at: Instant, task: Box<dyn FnOnce() + Send>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.scheduled += 1;
+        let sequence = queue.scheduled;
+        queue.tasks.push(Reverse(Scheduled { at, sequence, task }));
+        drop(queue);
+        self.scheduled.notify_one();
+    }
+
+    /// Waits for each task to be due and runs it, forever.
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let Some(Reverse(next)) = queue.tasks.peek() else {
+                queue = self.scheduled.wait(queue).unwrap();
+                continue;
+            };
+            if next.at > now {
+                let wait = next.at - now;
+                queue = self.scheduled.wait_timeout(queue, wait).unwrap().0;
+                continue;
+            }
+            if let Some(Reverse(due)) = queue.tasks.pop() {
+                // Tasks may take a while, and schedule others, so they run without the lock.
+                drop(queue);
+                (due.task)();
+                queue = self.queue.lock().unwrap();
+            }
+        }
+    }
+}
+
+/// Runs `f` once `delay` has elapsed, without blocking the caller.
+#[cfg(feature = "tokio")]
+pub fn after(delay: Duration, f: impl FnOnce() + Send + 'static) {
+    tokio::task::spawn(async move {
+        tokio::time::sleep(delay).await;
+        f();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MetricMetadata;
+
+    #[derive(Debug, Default)]
+    struct Recorded {
+        metrics: Mutex<Vec<MetricData>>,
+        removed: Mutex<Vec<String>>,
+    }
+
+    impl MetricSink for Recorded {
+        fn handle_metric(&self, metric: MetricData) {
+            self.metrics.lock().unwrap().push(metric);
+        }
+
+        fn handle_metadata(&self, _metadata: MetricMetadata) {}
+
+        fn remove_metric(&self, _kind: MetricKind, name: &str, _labels: &BTreeMap<String, String>) {
+            self.removed.lock().unwrap().push(name.to_string());
+        }
+    }
+
+    fn key(kind: MetricKind, name: &str) -> SeriesKey {
+        SeriesKey {
+            kind,
+            name: name.to_string(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn removes_series_once_every_connection_released_it() {
+        let recorded = Arc::new(Recorded::default());
+        let sink: Arc<dyn MetricSink> = recorded.clone();
+        let limits = Arc::new(SeriesLimits::default());
+        let tracker = Arc::new(SeriesTracker::new(DisconnectPolicy::Remove));
+        let shared = key(MetricKind::Gauge, "shared");
+        let own = key(MetricKind::Counter, "own");
+
+        tracker.acquire(shared.clone());
+        tracker.acquire(own.clone());
+        tracker.acquire(shared.clone());
+
+        tracker.release(&sink, &limits, HashSet::from([shared.clone(), own]));
+        assert_eq!(*recorded.removed.lock().unwrap(), ["own"]);
+
+        tracker.release(&sink, &limits, HashSet::from([shared]));
+        assert_eq!(*recorded.removed.lock().unwrap(), ["own", "shared"]);
+    }
+
+    #[test]
+    fn zeroes_orphaned_gauges_only() {
+        let recorded = Arc::new(Recorded::default());
+        let sink: Arc<dyn MetricSink> = recorded.clone();
+        let limits = Arc::new(SeriesLimits::default());
+        let tracker = Arc::new(SeriesTracker::new(DisconnectPolicy::Zero));
+        let gauge = key(MetricKind::Gauge, "in_flight");
+        let counter = key(MetricKind::Counter, "requests");
+
+        tracker.acquire(gauge.clone());
+        tracker.acquire(counter.clone());
+        tracker.release(&sink, &limits, HashSet::from([gauge, counter]));
+
+        let metrics = recorded.metrics.lock().unwrap().clone();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "in_flight");
+        assert!(matches!(metrics[0].operation, MetricOperation::SetGauge(v) if v == 0.0));
+        assert!(recorded.removed.lock().unwrap().is_empty());
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn runs_delayed_tasks_in_the_order_they_are_due() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for (delay, task) in [(60, "last"), (20, "first"), (40, "second")] {
+            let sender = sender.clone();
+            after(Duration::from_millis(delay), move || {
+                sender.send(task).unwrap();
+            });
+        }
+        let ran: Vec<_> = receiver.iter().take(3).collect();
+        assert_eq!(ran, ["first", "second", "last"]);
+    }
+
+    #[test]
+    fn ignores_series_it_does_not_track() {
+        let recorded = Arc::new(Recorded::default());
+        let sink: Arc<dyn MetricSink> = recorded.clone();
+        let tracker = Arc::new(SeriesTracker::new(DisconnectPolicy::Remove));
+        tracker.release(
+            &sink,
+            &Arc::new(SeriesLimits::default()),
+            HashSet::from([key(MetricKind::Gauge, "unknown")]),
+        );
+        assert!(recorded.removed.lock().unwrap().is_empty());
+    }
+}
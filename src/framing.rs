@@ -0,0 +1,345 @@
+//! Framing of encoded events on stream sockets.
+//!
+//! A frame is the two byte magic `0xC1 'M'`, the payload length and the CRC-32 of the payload as
+//! little-endian `u32`s, then the payload itself: one msgpack encoded
+//! [`MetricEvent`](crate::MetricEvent).
+//!
+//! Older recorders terminate each payload with a newline instead, which breaks whenever the
+//! payload itself contains one. `0xC1` is never used as a msgpack marker, so a frame can always be
+//! told apart from a newline terminated payload by its first byte, and both are accepted.
+//!
+//! When a frame fails its checksum, the decoder skips ahead to the next magic and carries on
+//! from there, so a single corrupt frame does not misalign everything after it. The same goes for
+//! a frame whose magic is corrupt: once a connection has sent a valid frame, anything else is
+//! skipped rather than read as a newline terminated payload, and before that a newline terminated
+//! payload that does not decode is skipped up to the next magic. Everything skipped until a frame
+//! passes its checksum is reported as one corrupt frame, however many stray magic bytes it
+//! contained. Frames larger than the decoder's maximum size are reported as soon as their size is
+//! known, before they have been buffered, unless the decoder is resynchronizing, when a magic
+//! announcing an oversize frame is more likely part of the corrupt data.
+
+use crate::events::MetricEvent;
+
+/// Marks the start of every frame.
+const MAGIC: [u8; 2] = [0xC1, b'M'];
+
+/// The size of the fixed part of a frame, before the payload.
+const HEADER: usize = MAGIC.len() + 4 + 4;
+
+/// Encodes a payload as a frame.
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let length = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+    let mut frame = Vec::with_capacity(HEADER + payload.len());
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Something found in the data received on a connection.
+#[derive(Debug)]
+pub enum Frame {
+    /// The payload of a frame, or a newline terminated payload including its newline.
+    Payload(Vec<u8>),
+    /// This many bytes were skipped as they did not form a valid frame.
+    Corrupt(usize),
+    /// A frame of at least this many bytes exceeded the maximum frame size. Everything buffered
+    /// has been discarded, as the end of the frame is unknown.
+    Oversize(usize),
+}
+
+/// Splits the data received on a connection into frames.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_size: usize,
+    /// Whether a valid frame has been decoded, so the connection no longer sends newline
+    /// terminated payloads.
+    framed: bool,
+    /// Bytes skipped since the last valid frame, reported once the decoder is back in sync.
+    skipped: usize,
+    /// The frame found once back in sync, returned after the skipped bytes have been reported.
+    resynced: Option<Frame>,
+}
+
+impl FrameDecoder {
+    /// Creates a decoder for payloads of at most `max_size` bytes.
+    pub const fn new(max_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_size,
+            framed: false,
+            skipped: 0,
+            resynced: None,
+        }
+    }
+
+    /// Appends data read from the connection.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame, or `None` if more data is needed.
+    ///
+    /// Everything skipped while resynchronizing is reported as a single [`Frame::Corrupt`], just
+    /// before the frame the decoder resynchronized on.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        if let Some(frame) = self.resynced.take() {
+            return Some(frame);
+        }
+        loop {
+            match self.decode()? {
+                Ok(frame) if self.skipped > 0 => {
+                    self.resynced = Some(frame);
+                    return Some(Frame::Corrupt(std::mem::take(&mut self.skipped)));
+                }
+                Ok(frame) => return Some(frame),
+                Err(skipped) => self.skipped += skipped,
+            }
+        }
+    }
+
+    /// Decodes the frame at the start of the buffer, or skips the bytes that do not form one,
+    /// returning how many were skipped.
+    fn decode(&mut self) -> Option<Result<Frame, usize>> {
+        match self.buffer.first() {
+            None => return None,
+            Some(&byte) if byte != MAGIC[0] && self.framed => return Some(Err(self.resync())),
+            Some(&byte) if byte != MAGIC[0] => return self.next_unframed(),
+            Some(_) => {}
+        }
+        if self.buffer.get(1).is_some_and(|&byte| byte != MAGIC[1]) {
+            return Some(Err(self.resync()));
+        }
+        let header = self.buffer.get(..HEADER)?;
+        let length = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        let checksum = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+        if length > self.max_size {
+            // While resynchronizing, the magic may as well be part of the corrupt data.
+            return Some(if self.skipped > 0 {
+                Err(self.resync())
+            } else {
+                Ok(self.oversize(length))
+            });
+        }
+        let payload = self.buffer.get(HEADER..HEADER + length)?;
+        if crc32fast::hash(payload) != checksum {
+            return Some(Err(self.resync()));
+        }
+        let payload = payload.to_vec();
+        self.buffer.drain(..HEADER + length);
+        self.framed = true;
+        Some(Ok(Frame::Payload(payload)))
+    }
+
+    /// Returns the newline terminated payload at the start of the buffer, unless it is the
+    /// remains of a corrupt frame followed by valid ones.
+    fn next_unframed(&mut self) -> Option<Result<Frame, usize>> {
+        let end = self.buffer.iter().position(|&byte| byte == b'\n');
+        let magic = self.buffer[..end.unwrap_or(self.buffer.len())]
+            .windows(MAGIC.len())
+            .position(|window| window == MAGIC);
+        match (end, magic) {
+            (Some(end), _) if end > self.max_size => Some(Ok(self.oversize(end))),
+            (Some(end), Some(magic))
+                if rmp_serde::from_slice::<MetricEvent>(&self.buffer[..=end]).is_err() =>
+            {
+                Some(Err(self.skip(magic)))
+            }
+            (Some(end), _) => Some(Ok(Frame::Payload(self.buffer.drain(..=end).collect()))),
+            // Without a newline yet, only a complete valid frame shows that this is not the
+            // start of a payload that happens to contain the magic.
+            (None, Some(magic)) if self.is_frame_at(magic) => Some(Err(self.skip(magic))),
+            (None, _) if self.buffer.len() > self.max_size => {
+                Some(Ok(self.oversize(self.buffer.len())))
+            }
+            (None, _) => None,
+        }
+    }
+
+    /// Whether a complete frame with a valid checksum starts at `start`.
+    fn is_frame_at(&self, start: usize) -> bool {
+        let Some(header) = self.buffer.get(start..start + HEADER) else {
+            return false;
+        };
+        let length = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        let checksum = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+        length <= self.max_size
+            && self
+                .buffer
+                .get(start + HEADER..start + HEADER + length)
+                .is_some_and(|payload| crc32fast::hash(payload) == checksum)
+    }
+
+    /// Returns whatever is left once the connection has been closed: a newline terminated
+    /// payload missing its newline, or an incomplete frame along with anything skipped before
+    /// it.
+    pub fn finish(self) -> Option<Frame> {
+        match self.buffer.first() {
+            None if self.skipped > 0 => Some(Frame::Corrupt(self.skipped)),
+            None => None,
+            Some(&byte) if byte == MAGIC[0] || self.framed || self.skipped > 0 => {
+                Some(Frame::Corrupt(self.skipped + self.buffer.len()))
+            }
+            Some(_) => Some(Frame::Payload(self.buffer)),
+        }
+    }
+
+    /// Discards everything buffered after finding a frame of `size` bytes.
+    fn oversize(&mut self, size: usize) -> Frame {
+        self.buffer.clear();
+        Frame::Oversize(size)
+    }
+
+    /// Skips the frame at the start of the buffer, up to the next possible magic, returning how
+    /// many bytes were skipped.
+    fn resync(&mut self) -> usize {
+        let skipped = self.buffer[1..]
+            .iter()
+            .position(|&byte| byte == MAGIC[0])
+            .map_or(self.buffer.len(), |position| position + 1);
+        self.skip(skipped)
+    }
+
+    /// Skips the first `skipped` bytes of the buffer.
+    fn skip(&mut self, skipped: usize) -> usize {
+        self.buffer.drain(..skipped);
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payloads(decoder: &mut FrameDecoder) -> Vec<Frame> {
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let mut data = encode(b"first");
+        data.extend(encode(b"second\nline"));
+        let mut decoder = FrameDecoder::new(64);
+        for chunk in data.chunks(3) {
+            decoder.extend(chunk);
+        }
+        let frames = payloads(&mut decoder);
+        assert!(matches!(&frames[..], [Frame::Payload(a), Frame::Payload(b)]
+            if a == b"first" && b == b"second\nline"));
+        assert!(decoder.finish().is_none());
+    }
+
+    #[test]
+    fn decodes_newline_terminated_payloads() {
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(b"legacy\nunterminated");
+        assert!(matches!(decoder.next_frame(), Some(Frame::Payload(p)) if p == b"legacy\n"));
+        assert!(decoder.next_frame().is_none());
+        assert!(matches!(decoder.finish(), Some(Frame::Payload(p)) if p == b"unterminated"));
+    }
+
+    #[test]
+    fn skips_frames_failing_their_checksum() {
+        let mut data = encode(b"corrupt");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        data.extend(encode(b"good"));
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&data);
+        let frames = payloads(&mut decoder);
+        assert!(matches!(&frames[..], [Frame::Corrupt(_), Frame::Payload(p)] if p == b"good"));
+    }
+
+    #[test]
+    fn skips_frames_with_a_corrupt_magic() {
+        let mut corrupt = encode(b"corrupt");
+        corrupt[0] = b'x';
+        let mut data = corrupt.clone();
+        data.extend(encode(b"first"));
+        data.extend(encode(b"second"));
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&data);
+        let frames = payloads(&mut decoder);
+        assert!(
+            matches!(&frames[..], [Frame::Corrupt(_), Frame::Payload(a), Frame::Payload(b)]
+            if a == b"first" && b == b"second")
+        );
+
+        decoder.extend(&corrupt);
+        decoder.extend(&encode(b"third"));
+        let frames = payloads(&mut decoder);
+        assert!(matches!(&frames[..], [Frame::Corrupt(_), Frame::Payload(p)] if p == b"third"));
+    }
+
+    #[test]
+    fn skips_unframed_payloads_that_do_not_decode() {
+        let mut data = b"garbage".to_vec();
+        data.extend(encode(b"line\nbreak"));
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&data);
+        let frames = payloads(&mut decoder);
+        assert!(
+            matches!(&frames[..], [Frame::Corrupt(7), Frame::Payload(p)] if p == b"line\nbreak")
+        );
+    }
+
+    /// A frame with a valid header whose payload was corrupted into `corrupt`.
+    fn corrupted(corrupt: &[u8]) -> Vec<u8> {
+        let mut frame = encode(&vec![0; corrupt.len()]);
+        frame[HEADER..].copy_from_slice(corrupt);
+        frame
+    }
+
+    #[test]
+    fn reports_each_resync_once() {
+        // Negative floats encode with a leading `0xC1` byte, each a possible magic.
+        let mut data = corrupted(&[0xC1, 0x01, 0xC1, 0xC1, 0x02, 0xC1]);
+        data.extend(encode(b"good"));
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&data);
+        let frames = payloads(&mut decoder);
+        assert!(matches!(&frames[..], [Frame::Corrupt(16), Frame::Payload(p)] if p == b"good"));
+    }
+
+    #[test]
+    fn ignores_oversize_lengths_while_resynchronizing() {
+        let mut corrupt = vec![0xC1, b'M'];
+        corrupt.extend(u32::MAX.to_le_bytes());
+        corrupt.extend([0; 4]);
+        let mut data = corrupted(&corrupt);
+        data.extend(encode(b"good"));
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&data);
+        let frames = payloads(&mut decoder);
+        assert!(matches!(&frames[..], [Frame::Corrupt(20), Frame::Payload(p)] if p == b"good"));
+    }
+
+    #[test]
+    fn reports_skipped_bytes_on_finish() {
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&corrupted(&[0xC1, 0x01]));
+        assert!(decoder.next_frame().is_none());
+        assert!(matches!(decoder.finish(), Some(Frame::Corrupt(12))));
+    }
+
+    #[test]
+    fn rejects_oversize_frames_from_their_header() {
+        let mut decoder = FrameDecoder::new(4);
+        decoder.extend(&encode(b"too large")[..HEADER]);
+        assert!(matches!(decoder.next_frame(), Some(Frame::Oversize(9))));
+        assert!(decoder.next_frame().is_none());
+
+        decoder.extend(b"no newline");
+        assert!(matches!(decoder.next_frame(), Some(Frame::Oversize(10))));
+    }
+
+    #[test]
+    fn reports_incomplete_frames_on_finish() {
+        let mut decoder = FrameDecoder::new(64);
+        decoder.extend(&encode(b"payload")[..HEADER + 2]);
+        assert!(decoder.next_frame().is_none());
+        assert!(matches!(decoder.finish(), Some(Frame::Corrupt(12))));
+    }
+}
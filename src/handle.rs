@@ -0,0 +1,278 @@
+//! Access to a running collector.
+
+use crate::{
+    collector::{Replay, Shared},
+    error::MetricsError,
+    events::MetricEvent,
+};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(not(feature = "tokio"))]
+use std::{
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    time::Duration,
+};
+#[cfg(feature = "tokio")]
+use tokio::sync::mpsc::{self, Receiver, Sender as SyncSender, error::TrySendError};
+
+/// An event received by the collector, as decoded from the wire.
+#[derive(Debug, Clone)]
+pub struct ReceivedEvent {
+    /// Identifies the connection the event was received on. Numbered from zero in the order
+    /// connections were accepted.
+    pub connection_id: u64,
+    /// When the collector received the event.
+    pub received_at: SystemTime,
+    pub event: MetricEvent,
+}
+
+/// The senders of every open [`Subscription`].
+#[derive(Debug, Default)]
+pub struct Subscribers(Mutex<Vec<SyncSender<ReceivedEvent>>>);
+
+impl Subscribers {
+    fn add(&self, capacity: usize) -> Receiver<ReceivedEvent> {
+        // A zero capacity would make every event a rendezvous, which `try_send` never completes.
+        let capacity = capacity.max(1);
+        #[cfg(not(feature = "tokio"))]
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        #[cfg(feature = "tokio")]
+        let (sender, receiver) = mpsc::channel(capacity);
+        self.0.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Returns `true` if there is at least one subscription, so events need building.
+    pub fn is_active(&self) -> bool {
+        !self.0.lock().unwrap().is_empty()
+    }
+
+    /// Sends an event to every subscription, skipping those that are full and forgetting those
+    /// that have been dropped.
+    pub fn publish(&self, event: &ReceivedEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::trace!("Subscriber is full, dropping event");
+                    true
+                }
+                Err(_) => false,
+            });
+    }
+}
+
+/// A handle to a running collector, returned by
+/// [`IPCCollector::start_collecting`](crate::IPCCollector::start_collecting).
+///
+/// Dropping the handle does not stop the collector.
+#[derive(Clone)]
+pub struct CollectorHandle {
+    pub(crate) shared: Arc<Shared>,
+    pub(crate) tcp_addrs: Vec<SocketAddr>,
+}
+
+impl CollectorHandle {
+    /// Returns the address the collector's first [TCP listener](crate::IPCCollector::tcp) is
+    /// bound to, or `None` if it only listens on local sockets.
+    #[must_use]
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        self.tcp_addrs.first().copied()
+    }
+
+    /// Returns the addresses of every TCP listener, in the order the
+    /// [listeners](crate::IPCCollector::listener) were added.
+    #[must_use]
+    pub fn tcp_addrs(&self) -> &[SocketAddr] {
+        &self.tcp_addrs
+    }
+
+    /// Describes every metric received so far to the sink again, along with the collector's
+    /// own series.
+    ///
+    /// Metadata is only sent by clients once, when a metric is first described, and passed on
+    /// to the sink as it arrives. A recorder installed after clients have connected, or swapped
+    /// at runtime, never sees it unless the collector describes the metrics again. Each metric
+    /// is described with the latest description received for it that did not
+    /// [conflict](crate::IPCCollector::on_metadata_conflict) with the first.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use metrics_exporter_prometheus::PrometheusBuilder;
+    /// use metrics_ipc_collector::IPCCollector;
+    /// # #[cfg(not(feature = "tokio"))]
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let handle = IPCCollector::default().start_collecting()?;
+    /// // Clients may connect and describe their metrics before the exporter is ready.
+    /// PrometheusBuilder::new().install()?;
+    /// handle.redescribe();
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(feature = "tokio")]
+    /// # fn main() {}
+    /// ```
+    pub fn redescribe(&self) {
+        self.shared.telemetry.describe();
+        for metadata in self.shared.metadata.descriptions() {
+            self.shared.sink.handle_metadata(metadata);
+        }
+    }
+
+    /// Subscribes to every event received by the collector from now on.
+    ///
+    /// Events are delivered as they were decoded, before any filtering, relabeling or merging.
+    /// Up to `capacity` events are buffered; a subscriber that falls further behind misses events
+    /// rather than slowing the collector down. Dropping the subscription unsubscribes.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # #[cfg(not(feature = "tokio"))]
+    /// # fn main() -> Result<(), metrics_ipc_collector::MetricsError> {
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let handle = IPCCollector::default().start_collecting()?;
+    /// for received in handle.subscribe(1024) {
+    ///     println!("{} {:?}", received.connection_id, received.event);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(feature = "tokio")]
+    /// # fn main() {}
+    /// ```
+    ///
+    /// # Feature Flags
+    /// - `tokio`: The subscription is an async `futures_core::Stream` instead of a
+    ///   blocking iterator.
+    #[must_use]
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        Subscription(self.shared.subscribers.add(capacity))
+    }
+
+    /// Feeds the frames of a [capture file](crate::IPCCollector::capture) back through the
+    /// collector, as if they were received again.
+    ///
+    /// Frames are replayed `speed` times faster than they were originally received, so `1.0`
+    /// keeps the original pace. [`f64::INFINITY`] replays them as fast as possible. Each captured
+    /// connection is replayed on a connection of its own, which is closed once the capture has
+    /// been replayed, with the peer and listener labels the original connection added.
+    ///
+    /// Rotated files can be replayed one after another, oldest first.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # #[cfg(not(feature = "tokio"))]
+    /// # fn main() -> Result<(), metrics_ipc_collector::MetricsError> {
+    /// use metrics_ipc_collector::IPCCollector;
+    /// let handle = IPCCollector::default().start_collecting()?;
+    /// handle.replay("frames.cap", 10.0)?;
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(feature = "tokio")]
+    /// # fn main() {}
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if `speed` is not a positive number, or if the file cannot be read or is
+    /// not a capture file. Frames replayed before the error are not undone.
+    #[cfg(not(feature = "tokio"))]
+    pub fn replay(&self, path: impl AsRef<Path>, speed: f64) -> Result<(), MetricsError> {
+        let mut replay = Replay::new(&self.shared, speed)?;
+        for frame in Replay::frames(path.as_ref())? {
+            let frame = frame?;
+            if let Some(delay) = replay.delay(&frame) {
+                std::thread::sleep(delay);
+            }
+            replay.feed(&frame);
+        }
+        Ok(())
+    }
+
+    /// Feeds the frames of a [capture file](crate::IPCCollector::capture) back through the
+    /// collector, as if they were received again.
+    ///
+    /// Frames are replayed `speed` times faster than they were originally received, so `1.0`
+    /// keeps the original pace. [`f64::INFINITY`] replays them as fast as possible. Each captured
+    /// connection is replayed on a connection of its own, which is closed once the capture has
+    /// been replayed, with the peer and listener labels the original connection added.
+    ///
+    /// Rotated files can be replayed one after another, oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if `speed` is not a positive number, or if the file cannot be read or is
+    /// not a capture file. Frames replayed before the error are not undone.
+    #[cfg(feature = "tokio")]
+    pub async fn replay(&self, path: impl AsRef<Path>, speed: f64) -> Result<(), MetricsError> {
+        let mut replay = Replay::new(&self.shared, speed)?;
+        for frame in Replay::frames(path.as_ref())? {
+            let frame = frame?;
+            if let Some(delay) = replay.delay(&frame) {
+                tokio::time::sleep(delay).await;
+            }
+            replay.feed(&frame);
+        }
+        Ok(())
+    }
+}
+
+/// The events received by a collector, returned by [`CollectorHandle::subscribe`].
+///
+/// Without the `tokio` feature this is a blocking [`Iterator`], which ends once the collector has
+/// stopped. With it, it is a `futures_core::Stream`.
+#[derive(Debug)]
+pub struct Subscription(Receiver<ReceivedEvent>);
+
+#[cfg(not(feature = "tokio"))]
+impl Subscription {
+    /// Returns the next event, or `None` if none is received within `timeout`.
+    #[must_use]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ReceivedEvent> {
+        self.0.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the next event if one is already buffered.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<ReceivedEvent> {
+        self.0.try_recv().ok()
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl Iterator for Subscription {
+    type Item = ReceivedEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.recv().ok()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Subscription {
+    /// Waits for the next event, returning `None` once the collector has stopped.
+    pub async fn recv(&mut self) -> Option<ReceivedEvent> {
+        self.0.recv().await
+    }
+
+    /// Returns the next event if one is already buffered.
+    pub fn try_recv(&mut self) -> Option<ReceivedEvent> {
+        self.0.try_recv().ok()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for Subscription {
+    type Item = ReceivedEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
@@ -17,11 +17,48 @@
 //!
 //! See README and examples for details.
 
+#[cfg(unix)]
+mod activation;
+#[cfg(unix)]
+mod auth;
+mod capture;
+mod cardinality;
 mod collector;
+mod conflict;
 mod error;
 mod events;
+mod expiry;
+mod framing;
+mod handle;
+mod listener;
+mod merge;
+mod pattern;
+#[cfg(unix)]
+mod peer;
 mod recorder;
+mod relabel;
+mod relay;
+mod sink;
+mod socket;
+mod store;
+mod telemetry;
 
+pub use cardinality::CardinalityOverflow;
 pub use collector::IPCCollector;
+pub use conflict::MetadataConflict;
 pub use error::MetricsError;
+pub use events::{
+    ClientHello, MetricData, MetricEvent, MetricKind, MetricMetadata, MetricOperation,
+};
+pub use expiry::DisconnectPolicy;
+pub use handle::{CollectorHandle, ReceivedEvent, Subscription};
+pub use listener::ListenerConfig;
+pub use merge::GaugeMerge;
+pub use pattern::NamePattern;
+#[cfg(unix)]
+pub use peer::PeerLabel;
 pub use recorder::{IPCRecorder, IPCRecorderBuilder};
+pub use relabel::{RelabelAction, RelabelRule, RelabelRules};
+pub use relay::{RelaySink, RelaySinkBuilder};
+pub use sink::{GlobalRecorderSink, MetricSink, RecorderSink};
+pub use store::{Distribution, MemoryStore, SeriesSnapshot, SeriesValue, Snapshot};
@@ -0,0 +1,694 @@
+//! Accepting client connections and reading frames from them.
+
+#[cfg(unix)]
+use crate::{auth::PeerAllowlist, peer::PeerLabel, socket::SocketPermissions};
+use crate::{
+    collector::{Connection, Shared},
+    error::MetricsError,
+    framing::{Frame, FrameDecoder},
+    socket::SocketPath,
+};
+use interprocess::local_socket::ListenerOptions;
+#[cfg(feature = "tokio")]
+use interprocess::local_socket::tokio::{Listener as LocalListener, prelude::*};
+#[cfg(not(feature = "tokio"))]
+use interprocess::local_socket::{
+    Listener as LocalListener, ListenerNonblockingMode, Stream, prelude::*,
+};
+#[cfg(unix)]
+use interprocess::os::unix::uds_local_socket;
+#[cfg(all(unix, not(feature = "tokio")))]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+#[cfg(not(feature = "tokio"))]
+use std::{io::Read, net::TcpListener, thread};
+#[cfg(unix)]
+use std::{
+    os::{fd::OwnedFd, unix::net::UnixListener},
+    path::Path,
+};
+#[cfg(all(unix, feature = "tokio"))]
+use tokio::net::UnixDatagram;
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpListener,
+    task, time,
+};
+
+/// How much is read from a stream at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// The largest datagram received in full. Longer datagrams are truncated and fail to decode.
+#[cfg(unix)]
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// How connections accepted by a listener are authorized and labelled.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionPolicy {
+    #[cfg(unix)]
+    pub allowlist: PeerAllowlist,
+    #[cfg(unix)]
+    pub peer_labels: Vec<PeerLabel>,
+    /// Labels added to every metric received through the listener.
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A socket for an [`IPCCollector`](crate::IPCCollector) to accept clients on, with its own
+/// permissions, allowlist and labels.
+///
+/// The collector always has one listener, configured with its own socket, permission and peer
+/// settings. Further listeners are added with
+/// [`IPCCollector::listener`](crate::IPCCollector::listener), and every listener feeds the same
+/// sink.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{IPCCollector, ListenerConfig, PeerLabel};
+/// let collector = IPCCollector::default()
+///     .socket_file("/run/metrics/system.sock")
+///     .socket_mode(0o600)
+///     .allow_uid(0)
+///     .listener(
+///         ListenerConfig::default()
+///             .socket_file("/run/metrics/user.sock")
+///             .socket_mode(0o666)
+///             .peer_labels(&[PeerLabel::Uid])
+///             .label("tier", "user"),
+///     );
+/// ```
+#[derive(Debug)]
+pub struct ListenerConfig {
+    pub(crate) socket_path: SocketPath,
+    pub(crate) tcp_port: Option<u16>,
+    pub(crate) bind_address: IpAddr,
+    #[cfg(unix)]
+    pub(crate) datagram: Option<PathBuf>,
+    #[cfg(unix)]
+    pub(crate) inherited: Option<OwnedFd>,
+    #[cfg(unix)]
+    pub(crate) permissions: SocketPermissions,
+    pub(crate) policy: ConnectionPolicy,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: SocketPath::default(),
+            tcp_port: None,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            #[cfg(unix)]
+            datagram: None,
+            #[cfg(unix)]
+            inherited: None,
+            #[cfg(unix)]
+            permissions: SocketPermissions::default(),
+            policy: ConnectionPolicy::default(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for ListenerConfig {
+    /// Listens on an already bound socket: a listening Unix domain or TCP socket, or a Unix
+    /// datagram socket.
+    ///
+    /// The socket, TCP and permission settings are ignored, and the socket file is left in
+    /// place on shutdown, as whoever bound the socket owns it.
+    fn from(fd: OwnedFd) -> Self {
+        Self {
+            inherited: Some(fd),
+            ..Self::default()
+        }
+    }
+}
+
+impl ListenerConfig {
+    /// Sets the name of the socket. See [`IPCCollector::socket`](crate::IPCCollector::socket).
+    #[must_use]
+    pub fn socket(mut self, socket_path: &str) -> Self {
+        self.socket_path = SocketPath::Name(socket_path.to_string());
+        self
+    }
+
+    /// Listens on a socket file at `path`. See
+    /// [`IPCCollector::socket_file`](crate::IPCCollector::socket_file).
+    #[must_use]
+    pub fn socket_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket_path = SocketPath::File(path.into());
+        self
+    }
+
+    /// Listens on a TCP port instead of a local socket. See
+    /// [`IPCCollector::tcp`](crate::IPCCollector::tcp).
+    #[must_use]
+    pub const fn tcp(mut self, port: u16) -> Self {
+        self.tcp_port = Some(port);
+        self
+    }
+
+    /// Sets the address the TCP listener binds to, 127.0.0.1 by default.
+    #[must_use]
+    pub const fn bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = address;
+        self
+    }
+
+    /// Receives one event per datagram on a Unix datagram socket at `path` instead of a stream
+    /// socket. See [`IPCCollector::datagram`](crate::IPCCollector::datagram).
+    #[cfg(unix)]
+    #[must_use]
+    pub fn datagram(mut self, path: impl Into<PathBuf>) -> Self {
+        self.datagram = Some(path.into());
+        self
+    }
+
+    /// Sets the file mode applied to the socket file. See
+    /// [`IPCCollector::socket_mode`](crate::IPCCollector::socket_mode).
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_mode(mut self, mode: u32) -> Self {
+        self.permissions.mode = Some(mode);
+        self
+    }
+
+    /// Sets the group that owns the socket file.
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_group(mut self, gid: u32) -> Self {
+        self.permissions.group = Some(gid);
+        self
+    }
+
+    /// Sets the mode of the directory containing the socket file when it has to be created. See
+    /// [`IPCCollector::socket_dir_mode`](crate::IPCCollector::socket_dir_mode).
+    #[cfg(unix)]
+    #[must_use]
+    pub const fn socket_dir_mode(mut self, mode: u32) -> Self {
+        self.permissions.dir_mode = Some(mode);
+        self
+    }
+
+    /// Allows processes running as `uid` to connect to this listener. See
+    /// [`IPCCollector::allow_uid`](crate::IPCCollector::allow_uid).
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.policy.allowlist.uids.push(uid);
+        self
+    }
+
+    /// Allows processes whose effective group is `gid` to connect to this listener.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.policy.allowlist.gids.push(gid);
+        self
+    }
+
+    /// Allows the process with id `pid` to connect to this listener.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn allow_pid(mut self, pid: u32) -> Self {
+        self.policy.allowlist.pids.push(pid);
+        self
+    }
+
+    /// Adds labels identifying the sending process to every metric received through this
+    /// listener. See [`IPCCollector::peer_labels`](crate::IPCCollector::peer_labels).
+    #[cfg(unix)]
+    #[must_use]
+    pub fn peer_labels(mut self, labels: &[PeerLabel]) -> Self {
+        self.policy.peer_labels = labels.to_vec();
+        self
+    }
+
+    /// Adds a fixed label to every metric received through this listener. See
+    /// [`IPCCollector::label`](crate::IPCCollector::label).
+    #[must_use]
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.policy
+            .labels
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Binds the configured socket, or takes over the inherited one.
+    ///
+    /// With the `tokio` feature this must be called from within a Tokio runtime.
+    pub(crate) fn bind(mut self) -> Result<Bound, MetricsError> {
+        let (listener, socket_file) = self.bind_socket()?;
+        Ok(Bound {
+            listener,
+            socket_file,
+            policy: Arc::new(self.policy),
+        })
+    }
+
+    /// Returns the listener along with the socket file to remove on shutdown, if any.
+    fn bind_socket(&mut self) -> Result<(Listener, Option<PathBuf>), MetricsError> {
+        #[cfg(unix)]
+        if let Some(fd) = self.inherited.take() {
+            return Ok((Listener::from_fd(fd)?, None));
+        }
+        #[cfg(unix)]
+        if let Some(path) = self.datagram.take() {
+            return Ok((bind_datagram(&path, &self.permissions)?, Some(path)));
+        }
+        if let Some(port) = self.tcp_port {
+            let address = SocketAddr::new(self.bind_address, port);
+            return Ok((Listener::bind_tcp(address)?, None));
+        }
+        #[cfg(unix)]
+        let listener = bind_local(&self.socket_path, &self.permissions)?;
+        #[cfg(not(unix))]
+        let listener = bind_local(&self.socket_path)?;
+        Ok((listener, self.socket_path.file()))
+    }
+}
+
+/// Binds a Unix datagram socket, replacing any stale socket file.
+#[cfg(unix)]
+fn bind_datagram(path: &Path, permissions: &SocketPermissions) -> Result<Listener, MetricsError> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    permissions.prepare_dir(path)?;
+    let socket = permissions.bind_datagram(path)?;
+    permissions.apply_file(path)?;
+    #[cfg(feature = "tokio")]
+    let socket = {
+        socket.set_nonblocking(true)?;
+        UnixDatagram::from_std(socket)?
+    };
+    Ok(Listener::Datagram(socket))
+}
+
+/// Binds a local socket, replacing any stale socket file.
+fn bind_local(
+    socket_path: &SocketPath,
+    #[cfg(unix)] permissions: &SocketPermissions,
+) -> Result<Listener, MetricsError> {
+    let socket_file = socket_path.file();
+    if let Some(socket_file) = &socket_file
+        && socket_file.exists()
+    {
+        std::fs::remove_file(socket_file)?;
+    }
+
+    let options = ListenerOptions::new().name(socket_path.name()?);
+    #[cfg(unix)]
+    let options = if let Some(socket_file) = &socket_file {
+        permissions.prepare_dir(socket_file)?;
+        permissions.listener_options(options)
+    } else {
+        permissions.warn_if_namespaced(socket_path);
+        options
+    };
+
+    #[cfg(not(feature = "tokio"))]
+    let listener = options.create_sync()?;
+    #[cfg(feature = "tokio")]
+    let listener = options.create_tokio()?;
+
+    #[cfg(unix)]
+    if let Some(socket_file) = &socket_file {
+        permissions.apply_file(socket_file)?;
+    }
+    Ok(Listener::Local(listener))
+}
+
+/// A listener ready to accept clients, along with the settings it accepts them with.
+pub struct Bound {
+    pub listener: Listener,
+    /// The socket file to remove on shutdown, if the collector created one.
+    socket_file: Option<PathBuf>,
+    policy: Arc<ConnectionPolicy>,
+}
+
+impl Bound {
+    /// Accepts clients on a thread (default) or Tokio task of its own until the listener fails.
+    pub fn spawn(self, shared: &Arc<Shared>) {
+        let shared = Arc::clone(shared);
+
+        #[cfg(not(feature = "tokio"))]
+        thread::spawn(move || {
+            if let Err(e) = run(&self.listener, &shared, &self.policy) {
+                log::error!("Metrics collector error: {e}");
+            }
+            self.remove_socket_file();
+        });
+
+        #[cfg(feature = "tokio")]
+        task::spawn(async move {
+            if let Err(e) = run(&self.listener, &shared, &self.policy).await {
+                log::error!("Metrics collector error: {e}");
+            }
+            self.remove_socket_file();
+        });
+    }
+
+    // Clean up socket file on shutdown
+    fn remove_socket_file(&self) {
+        if let Some(socket_file) = &self.socket_file {
+            let _ = std::fs::remove_file(socket_file);
+        }
+    }
+}
+
+/// A bound socket the collector accepts clients on.
+pub enum Listener {
+    Local(LocalListener),
+    Tcp(TcpListener),
+    /// A Unix datagram socket, served as a single connection receiving one frame per datagram.
+    #[cfg(unix)]
+    Datagram(UnixDatagram),
+}
+
+impl Listener {
+    /// Binds a TCP listener to `addr`.
+    ///
+    /// With the `tokio` feature this must be called from within a Tokio runtime.
+    pub fn bind_tcp(addr: SocketAddr) -> io::Result<Self> {
+        Self::from_std_tcp(std::net::TcpListener::bind(addr)?)
+    }
+
+    /// Takes over an already bound socket: a listening Unix domain or TCP socket, or a Unix
+    /// datagram socket.
+    ///
+    /// With the `tokio` feature this must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    /// Returns an error for any other socket, including stream sockets that are not listening,
+    /// which would fail every `accept()`.
+    #[cfg(unix)]
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let socket_type = socket_option(&fd, libc::SO_TYPE)?;
+        let listening =
+            socket_type == libc::SOCK_STREAM && socket_option(&fd, libc::SO_ACCEPTCONN)? != 0;
+        let listener = UnixListener::from(fd);
+        // Only Unix domain sockets have a Unix socket address.
+        let is_unix = listener.local_addr().is_ok();
+        if socket_type == libc::SOCK_DGRAM && is_unix {
+            let socket = std::os::unix::net::UnixDatagram::from(OwnedFd::from(listener));
+            #[cfg(not(feature = "tokio"))]
+            socket.set_nonblocking(false)?;
+            #[cfg(feature = "tokio")]
+            let socket = {
+                socket.set_nonblocking(true)?;
+                UnixDatagram::from_std(socket)?
+            };
+            return Ok(Self::Datagram(socket));
+        }
+        if !listening {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "inherited socket is neither a listening stream socket nor a Unix datagram socket",
+            ));
+        }
+        if !is_unix {
+            let listener = std::net::TcpListener::from(OwnedFd::from(listener));
+            listener.local_addr()?;
+            return Self::from_std_tcp(listener);
+        }
+        #[cfg(not(feature = "tokio"))]
+        let listener = uds_local_socket::Listener::from(listener);
+        #[cfg(feature = "tokio")]
+        let listener = uds_local_socket::tokio::Listener::try_from(OwnedFd::from(listener))?;
+        Ok(Self::Local(listener.into()))
+    }
+
+    fn from_std_tcp(listener: std::net::TcpListener) -> io::Result<Self> {
+        #[cfg(not(feature = "tokio"))]
+        listener.set_nonblocking(false)?;
+        #[cfg(feature = "tokio")]
+        let listener = {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)?
+        };
+        Ok(Self::Tcp(listener))
+    }
+
+    /// Returns the address of a TCP listener, which is how to find the port when binding to 0.
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Reads an integer `SOL_SOCKET` option of a socket.
+#[cfg(unix)]
+fn socket_option(fd: &OwnedFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    use std::os::fd::AsRawFd;
+
+    let mut value: libc::c_int = 0;
+    let mut length =
+        libc::socklen_t::try_from(std::mem::size_of::<libc::c_int>()).map_err(io::Error::other)?;
+    // SAFETY: `value` and `length` describe a buffer large enough for an integer option.
+    let result = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&raw mut value).cast(),
+            &raw mut length,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// TCP peers and datagram senders have no credentials, so they never match a peer allowlist.
+fn no_credentials<T>() -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "peer credentials are only available for local stream sockets",
+    ))
+}
+
+// We can safely filter out any errors from the incoming stream
+#[cfg(not(feature = "tokio"))]
+fn filter_streams(conn: io::Result<Stream>) -> Option<Stream> {
+    conn.ok()
+}
+
+#[cfg(not(feature = "tokio"))]
+fn run(
+    listener: &Listener,
+    shared: &Arc<Shared>,
+    policy: &ConnectionPolicy,
+) -> Result<(), MetricsError> {
+    match listener {
+        Listener::Local(listener) => {
+            // Accepting and reading both block: each runs on a dedicated thread, and nonblocking
+            // reads would drop partially received frames.
+            listener.set_nonblocking(ListenerNonblockingMode::Neither)?;
+            for stream in listener.incoming().filter_map(filter_streams) {
+                let Some(connection) = shared.accept(policy, stream.peer_creds()) else {
+                    continue;
+                };
+                if let Err(e) = stream.set_recv_timeout(connection.idle_timeout()) {
+                    log::warn!("Failed to set idle timeout: {e}");
+                }
+                thread::spawn(move || serve(stream, connection));
+            }
+        }
+        Listener::Tcp(listener) => {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                let Some(connection) = shared.accept(policy, no_credentials()) else {
+                    continue;
+                };
+                if let Err(e) = stream.set_read_timeout(connection.idle_timeout()) {
+                    log::warn!("Failed to set idle timeout: {e}");
+                }
+                thread::spawn(move || serve(stream, connection));
+            }
+        }
+        #[cfg(unix)]
+        Listener::Datagram(socket) => {
+            if let Some(connection) = shared.accept(policy, no_credentials()) {
+                serve_datagrams(socket, connection);
+            } else {
+                log::warn!("Datagram senders cannot match a peer allowlist, closing socket");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Passes a frame to the connection, returning it unless it has been dropped for sending an
+/// oversize frame or too many bad frames in a row.
+fn deliver(frame: Frame, mut connection: Connection) -> Option<Connection> {
+    match frame {
+        Frame::Payload(payload) => connection.receive(&payload),
+        Frame::Corrupt(skipped) => connection.corrupt(skipped),
+        Frame::Oversize(size) => {
+            connection.oversize(size);
+            connection.dropped();
+            return None;
+        }
+    }
+    if connection.is_failing() {
+        connection.drop_failing();
+        return None;
+    }
+    Some(connection)
+}
+
+/// Reads frames until the stream ends or times out, then closes the connection.
+#[cfg(not(feature = "tokio"))]
+fn serve(mut stream: impl Read, mut connection: Connection) {
+    let mut decoder = FrameDecoder::new(connection.max_frame_size());
+    let mut buffer = [0; READ_SIZE];
+
+    loop {
+        match stream.read(&mut buffer) {
+            // The stream's read timeout is the idle timeout.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return connection.timed_out();
+            }
+            Ok(0) => break,
+            Ok(read) => {
+                decoder.extend(&buffer[..read]);
+                while let Some(frame) = decoder.next_frame() {
+                    let Some(open) = deliver(frame, connection) else {
+                        return;
+                    };
+                    connection = open;
+                }
+            }
+            // If we encounter an error reading from the stream, we just skip it
+            Err(_) => {}
+        }
+    }
+    if let Some(frame) = decoder.finish() {
+        let Some(open) = deliver(frame, connection) else {
+            return;
+        };
+        connection = open;
+    }
+    connection.close();
+}
+
+#[cfg(feature = "tokio")]
+async fn run(
+    listener: &Listener,
+    shared: &Arc<Shared>,
+    policy: &ConnectionPolicy,
+) -> Result<(), MetricsError> {
+    match listener {
+        Listener::Local(listener) => loop {
+            if let Ok(stream) = listener.accept().await {
+                let Some(connection) = shared.accept(policy, stream.peer_creds()) else {
+                    continue;
+                };
+                task::spawn(serve(stream, connection));
+            }
+        },
+        Listener::Tcp(listener) => loop {
+            if let Ok((stream, _)) = listener.accept().await {
+                let Some(connection) = shared.accept(policy, no_credentials()) else {
+                    continue;
+                };
+                task::spawn(serve(stream, connection));
+            }
+        },
+        #[cfg(unix)]
+        Listener::Datagram(socket) => {
+            if let Some(connection) = shared.accept(policy, no_credentials()) {
+                serve_datagrams(socket, connection).await;
+            } else {
+                log::warn!("Datagram senders cannot match a peer allowlist, closing socket");
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(unix, not(feature = "tokio")))]
+fn serve_datagrams(socket: &UnixDatagram, mut connection: Connection) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    let mut frame = Vec::new();
+    loop {
+        match socket.recv(&mut buffer) {
+            Ok(len) if len > connection.max_frame_size() => connection.oversize(len),
+            Ok(len) => {
+                frame.clear();
+                frame.extend_from_slice(&buffer[..len]);
+                connection.receive(&frame);
+            }
+            Err(e) => log::debug!("Failed to receive datagram: {e}"),
+        }
+    }
+}
+
+/// Reads frames until the stream ends or times out, then closes the connection.
+#[cfg(feature = "tokio")]
+async fn serve(mut stream: impl AsyncRead + Unpin, mut connection: Connection) {
+    let mut decoder = FrameDecoder::new(connection.max_frame_size());
+    let mut buffer = [0; READ_SIZE];
+
+    loop {
+        let read = stream.read(&mut buffer);
+        let read = match connection.idle_timeout() {
+            Some(timeout) => match time::timeout(timeout, read).await {
+                Ok(read) => read,
+                Err(_) => return connection.timed_out(),
+            },
+            None => read.await,
+        };
+        match read {
+            Ok(0) => break,
+            Ok(read) => {
+                decoder.extend(&buffer[..read]);
+                while let Some(frame) = decoder.next_frame() {
+                    let Some(open) = deliver(frame, connection) else {
+                        return;
+                    };
+                    connection = open;
+                }
+            }
+            // If we encounter an error reading from the stream, we just skip it
+            Err(_) => {}
+        }
+    }
+    if let Some(frame) = decoder.finish() {
+        let Some(open) = deliver(frame, connection) else {
+            return;
+        };
+        connection = open;
+    }
+    connection.close();
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+async fn serve_datagrams(socket: &UnixDatagram, mut connection: Connection) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    let mut frame = Vec::new();
+    loop {
+        match socket.recv(&mut buffer).await {
+            Ok(len) if len > connection.max_frame_size() => connection.oversize(len),
+            Ok(len) => {
+                frame.clear();
+                frame.extend_from_slice(&buffer[..len]);
+                connection.receive(&frame);
+            }
+            Err(e) => log::debug!("Failed to receive datagram: {e}"),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Merging of gauges and absolute counters reported by several clients.
+//!
+//! Every client setting the same gauge or counter would otherwise overwrite the value of the
+//! others. The collector keeps the value each source reported and forwards the merged value
+//! instead.
+
+use crate::{
+    events::{MetricData, MetricKind, MetricOperation},
+    pattern::NamePattern,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Mutex,
+};
+
+/// The label added to gauges merged with [`GaugeMerge::PerSource`].
+const SOURCE_LABEL: &str = "source";
+
+/// The process a metric was received from, as far as per-source state is concerned.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// A client that has not identified itself, known only by its connection number.
+    Connection(u64),
+    /// A client that identified itself with a client id, which survives reconnects.
+    Client(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection(id) => write!(f, "{id}"),
+            Self::Client(id) => f.write_str(id),
+        }
+    }
+}
+
+/// How gauges reported by several clients are combined into the exported value.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{GaugeMerge, IPCCollector};
+/// let collector = IPCCollector::default()
+///     .gauge_merge("*_queue_depth", GaugeMerge::Sum)
+///     .gauge_merge("memory_high_watermark_bytes", GaugeMerge::Max);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GaugeMerge {
+    /// Export the sum of the values reported by every source.
+    Sum,
+    /// Export the largest value reported by any source.
+    Max,
+    /// Export the smallest value reported by any source.
+    Min,
+    /// Export the value of the source that wrote most recently.
+    LastWrite,
+    /// Export a separate series per source, distinguished by a `source` label.
+    PerSource,
+}
+
+/// The values each source reported for a single gauge, least recently written first.
+#[derive(Debug, Default)]
+struct SourceValues(Vec<(Source, f64)>);
+
+impl SourceValues {
+    fn apply(&mut self, source: &Source, operation: &MetricOperation) {
+        let position = self.0.iter().position(|(s, _)| s == source);
+        let (source, value) =
+            position.map_or_else(|| (source.clone(), 0.0), |position| self.0.remove(position));
+        let value = match *operation {
+            MetricOperation::SetGauge(v) => v,
+            MetricOperation::IncrementGauge(v) => value + v,
+            MetricOperation::DecrementGauge(v) => value - v,
+            _ => value,
+        };
+        self.0.push((source, value));
+    }
+
+    fn merged(&self, merge: GaugeMerge) -> f64 {
+        let values = self.0.iter().map(|(_, value)| *value);
+        match merge {
+            GaugeMerge::Sum => values.sum(),
+            GaugeMerge::Max => values.fold(f64::NEG_INFINITY, f64::max),
+            GaugeMerge::Min => values.fold(f64::INFINITY, f64::min),
+            GaugeMerge::LastWrite | GaugeMerge::PerSource => {
+                self.0.last().map_or(0.0, |(_, value)| *value)
+            }
+        }
+    }
+}
+
+/// Applies the configured merge rules to gauges, keeping the per-source values they need.
+#[derive(Debug, Default)]
+pub struct GaugeMerger {
+    rules: Vec<(NamePattern, GaugeMerge)>,
+    gauges: Mutex<HashMap<GaugeKey, (GaugeMerge, SourceValues)>>,
+}
+
+/// The name and labels of a merged gauge.
+type GaugeKey = (String, BTreeMap<String, String>);
+
+impl GaugeMerger {
+    /// Adds a rule merging gauges whose name matches `pattern`. Earlier rules take precedence.
+    pub fn add_rule(&mut self, pattern: NamePattern, merge: GaugeMerge) {
+        self.rules.push((pattern, merge));
+    }
+
+    fn rule(&self, name: &str) -> Option<GaugeMerge> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(name))
+            .map(|(_, merge)| *merge)
+    }
+
+    /// Labels a gauge merged with [`GaugeMerge::PerSource`] with the `source` it was reported
+    /// by, making it a series of its own before the series limits see it.
+    pub fn label_source(&self, source: &Source, mut metric: MetricData) -> MetricData {
+        if metric.operation.kind() == MetricKind::Gauge
+            && self.rule(&metric.name) == Some(GaugeMerge::PerSource)
+        {
+            metric
+                .labels
+                .insert(SOURCE_LABEL.to_string(), source.to_string());
+        }
+        metric
+    }
+
+    /// Merges a metric reported by `source`, returning the metric to forward to the sink.
+    ///
+    /// Metrics that are not gauges, match no rule, or are merged per source and so already
+    /// [labeled](Self::label_source), are returned unchanged.
+    pub fn apply(&self, source: &Source, mut metric: MetricData) -> MetricData {
+        if metric.operation.kind() != MetricKind::Gauge {
+            return metric;
+        }
+        let merge = match self.rule(&metric.name) {
+            None | Some(GaugeMerge::PerSource) => return metric,
+            Some(merge) => merge,
+        };
+
+        let mut gauges = self.gauges.lock().unwrap();
+        let (_, values) = gauges
+            .entry((metric.name.clone(), metric.labels.clone()))
+            .or_insert_with(|| (merge, SourceValues::default()));
+        values.apply(source, &metric.operation);
+        let merged = values.merged(merge);
+        drop(gauges);
+
+        metric.operation = MetricOperation::SetGauge(merged);
+        metric
+    }
+
+    /// Drops every value reported by `source`, returning the re-merged gauges that are still
+    /// reported by other sources.
+    pub fn remove_source(&self, source: &Source) -> Vec<MetricData> {
+        let mut gauges = self.gauges.lock().unwrap();
+        let mut merged = Vec::new();
+        gauges.retain(|(name, labels), (merge, values)| {
+            let before = values.0.len();
+            values.0.retain(|(s, _)| s != source);
+            if values.0.len() != before && !values.0.is_empty() {
+                merged.push(MetricData {
+                    name: name.clone(),
+                    labels: labels.clone(),
+                    operation: MetricOperation::SetGauge(values.merged(*merge)),
+                });
+            }
+            !values.0.is_empty()
+        });
+        merged
+    }
+}
+
+/// Turns absolute counter values reported by each source into increments of the exported
+/// counter, so that it holds the sum of every source's total.
+///
+/// A source whose total goes backwards is assumed to have restarted and counted up from zero.
+#[derive(Debug, Default)]
+pub struct CounterTotals {
+    totals: Mutex<HashMap<(Source, GaugeKey), u64>>,
+}
+
+impl CounterTotals {
+    /// Converts a counter reported by `source` into the metric to forward to the sink.
+    ///
+    /// Increments are forwarded unchanged but still added to the source's total, so a later
+    /// absolute value is compared against everything the source has counted.
+    pub fn apply(&self, source: &Source, mut metric: MetricData) -> MetricData {
+        let key = match metric.operation {
+            MetricOperation::SetCounter(_) | MetricOperation::IncrementCounter(_) => {
+                (source.clone(), (metric.name.clone(), metric.labels.clone()))
+            }
+            _ => return metric,
+        };
+
+        let mut totals = self.totals.lock().unwrap();
+        match metric.operation {
+            MetricOperation::SetCounter(value) => {
+                let delta = match totals.insert(key, value) {
+                    Some(previous) if value >= previous => value - previous,
+                    // First report from this source, or it restarted and counted up from zero.
+                    _ => value,
+                };
+                metric.operation = MetricOperation::IncrementCounter(delta);
+            }
+            MetricOperation::IncrementCounter(value) => {
+                // Only counters that have been set absolutely need a running total.
+                if let Some(total) = totals.get_mut(&key) {
+                    *total = total.saturating_add(value);
+                }
+            }
+            _ => {}
+        }
+        drop(totals);
+        metric
+    }
+
+    /// Drops the totals reported by `source`.
+    pub fn remove_source(&self, source: &Source) {
+        self.totals.lock().unwrap().retain(|(s, _), _| s != source);
+    }
+}
+
+#[cfg(test)]
+// Every value compared is a sum of small integers, which floats represent exactly.
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    fn gauge(operation: MetricOperation) -> MetricData {
+        MetricData {
+            name: "in_flight".to_string(),
+            labels: BTreeMap::new(),
+            operation,
+        }
+    }
+
+    fn value(metric: &MetricData) -> f64 {
+        match metric.operation {
+            MetricOperation::SetGauge(value) => value,
+            ref operation => panic!("expected a gauge set, got {operation:?}"),
+        }
+    }
+
+    fn merger(merge: GaugeMerge) -> GaugeMerger {
+        let mut merger = GaugeMerger::default();
+        merger.add_rule(NamePattern::glob("in_*"), merge);
+        merger
+    }
+
+    #[test]
+    fn merges_values_of_every_source() {
+        let (a, b) = (Source::Connection(1), Source::Client("b".to_string()));
+        let sum = merger(GaugeMerge::Sum);
+        sum.apply(&a, gauge(MetricOperation::SetGauge(2.0)));
+        assert_eq!(
+            value(&sum.apply(&b, gauge(MetricOperation::SetGauge(3.0)))),
+            5.0
+        );
+        assert_eq!(
+            value(&sum.apply(&a, gauge(MetricOperation::IncrementGauge(1.0)))),
+            6.0
+        );
+
+        let max = merger(GaugeMerge::Max);
+        max.apply(&a, gauge(MetricOperation::SetGauge(2.0)));
+        assert_eq!(
+            value(&max.apply(&b, gauge(MetricOperation::SetGauge(1.0)))),
+            2.0
+        );
+
+        let min = merger(GaugeMerge::Min);
+        min.apply(&a, gauge(MetricOperation::SetGauge(2.0)));
+        assert_eq!(
+            value(&min.apply(&b, gauge(MetricOperation::SetGauge(1.0)))),
+            1.0
+        );
+    }
+
+    #[test]
+    fn remerges_when_a_source_is_removed() {
+        let (a, b) = (Source::Connection(1), Source::Connection(2));
+        let merger = merger(GaugeMerge::Sum);
+        merger.apply(&a, gauge(MetricOperation::SetGauge(2.0)));
+        merger.apply(&b, gauge(MetricOperation::SetGauge(3.0)));
+
+        let remerged = merger.remove_source(&a);
+        assert_eq!(remerged.len(), 1);
+        assert_eq!(value(&remerged[0]), 3.0);
+        // The last source going away leaves nothing to re-merge.
+        assert!(merger.remove_source(&b).is_empty());
+    }
+
+    #[test]
+    fn labels_per_source_gauges_and_ignores_unmatched() {
+        let merger = merger(GaugeMerge::PerSource);
+        let source = Source::Client("worker".to_string());
+        let metric = merger.label_source(&source, gauge(MetricOperation::IncrementGauge(1.0)));
+        let metric = merger.apply(&source, metric);
+        assert_eq!(metric.labels[SOURCE_LABEL], "worker");
+        assert!(matches!(
+            metric.operation,
+            MetricOperation::IncrementGauge(v) if v == 1.0
+        ));
+
+        let mut other = gauge(MetricOperation::IncrementGauge(1.0));
+        other.name = "queue_depth".to_string();
+        let source = Source::Connection(1);
+        let metric = merger.apply(&source, merger.label_source(&source, other));
+        assert!(metric.labels.is_empty());
+        assert!(matches!(
+            metric.operation,
+            MetricOperation::IncrementGauge(_)
+        ));
+    }
+
+    #[test]
+    fn turns_absolute_counters_into_increments() {
+        let counter = |operation| MetricData {
+            name: "jobs".to_string(),
+            labels: BTreeMap::new(),
+            operation,
+        };
+        let increment = |metric: MetricData| match metric.operation {
+            MetricOperation::IncrementCounter(value) => value,
+            operation => panic!("expected an increment, got {operation:?}"),
+        };
+        let totals = CounterTotals::default();
+        let source = Source::Connection(1);
+
+        assert_eq!(
+            increment(totals.apply(&source, counter(MetricOperation::SetCounter(10)))),
+            10
+        );
+        assert_eq!(
+            increment(totals.apply(&source, counter(MetricOperation::IncrementCounter(2)))),
+            2
+        );
+        assert_eq!(
+            increment(totals.apply(&source, counter(MetricOperation::SetCounter(15)))),
+            3
+        );
+        // Going backwards means the source restarted.
+        assert_eq!(
+            increment(totals.apply(&source, counter(MetricOperation::SetCounter(4)))),
+            4
+        );
+
+        totals.remove_source(&source);
+        assert_eq!(
+            increment(totals.apply(&source, counter(MetricOperation::SetCounter(6)))),
+            6
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Patterns used to select metrics by name.
+
+use crate::error::MetricsError;
+use regex::Regex;
+
+/// A glob pattern matched against metric names.
+///
+/// `*` matches any run of characters, including none, and `?` matches exactly one character.
+/// Every other character matches itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob(Vec<char>);
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self(pattern.chars().collect())
+    }
+
+    /// Returns `true` if `name` matches the whole pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        let (mut p, mut n) = (0, 0);
+        // Position of the last `*` seen and the name position it was tried against.
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while n < name.len() {
+            match self.0.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    // Let the last `*` swallow one more character and retry from there.
+                    Some((star, matched)) => {
+                        backtrack = Some((star, matched + 1));
+                        p = star + 1;
+                        n = matched + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.0[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// A pattern matched against metric names, either a glob or a regular expression.
+///
+/// Globs are created from strings: `*` matches any run of characters and `?` any single
+/// character. Regular expressions must match the whole name.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::NamePattern;
+/// let glob = NamePattern::from("tokio_*");
+/// let regex = NamePattern::regex("hyper_(client|server)_.*")?;
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct NamePattern(Matcher);
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Glob(Glob),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    /// Creates a glob pattern.
+    #[must_use]
+    pub fn glob(pattern: &str) -> Self {
+        Self(Matcher::Glob(Glob::new(pattern)))
+    }
+
+    /// Creates a regular expression pattern, anchored at both ends.
+    ///
+    /// # Errors
+    /// Returns an error if `pattern` is not a valid regular expression.
+    pub fn regex(pattern: &str) -> Result<Self, MetricsError> {
+        Ok(Self(Matcher::Regex(Regex::new(&format!(
+            "^(?:{pattern})$"
+        ))?)))
+    }
+
+    /// Returns `true` if `name` matches the pattern.
+    #[must_use]
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.0 {
+            Matcher::Glob(glob) => glob.matches(name),
+            Matcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl From<&str> for NamePattern {
+    fn from(pattern: &str) -> Self {
+        Self::glob(pattern)
+    }
+}
+
+/// Allow and deny lists deciding which metric names are passed on to the sink.
+#[derive(Debug, Clone, Default)]
+pub struct NameFilter {
+    pub allow: Vec<NamePattern>,
+    pub deny: Vec<NamePattern>,
+}
+
+impl NameFilter {
+    /// Returns `true` if `name` matches an allowed pattern, or none are configured, and matches
+    /// no denied pattern.
+    pub fn permits(&self, name: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|pattern| pattern.matches(name)))
+            && !self.deny.iter().any(|pattern| pattern.matches(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        let glob = Glob::new("http_*_total");
+        assert!(glob.matches("http_requests_total"));
+        assert!(glob.matches("http__total"));
+        assert!(!glob.matches("http_requests"));
+        assert!(!glob.matches("grpc_requests_total"));
+
+        assert!(Glob::new("*").matches(""));
+        assert!(Glob::new("a*b*c").matches("abxbc"));
+        assert!(Glob::new("job_?").matches("job_1"));
+        assert!(!Glob::new("job_?").matches("job_12"));
+        assert!(!Glob::new("").matches("a"));
+    }
+
+    #[test]
+    fn anchors_regexes() {
+        let regex = NamePattern::regex("hyper_(client|server)").unwrap();
+        assert!(regex.matches("hyper_client"));
+        assert!(!regex.matches("hyper_client_requests"));
+        assert!(!regex.matches("my_hyper_server"));
+        assert!(NamePattern::regex("(").is_err());
+    }
+
+    #[test]
+    fn filters_by_allow_and_deny_lists() {
+        let filter = NameFilter::default();
+        assert!(filter.permits("anything"));
+
+        let filter = NameFilter {
+            allow: vec!["app_*".into()],
+            deny: vec!["app_debug_*".into()],
+        };
+        assert!(filter.permits("app_requests"));
+        assert!(!filter.permits("app_debug_allocations"));
+        assert!(!filter.permits("tokio_tasks"));
+    }
+}
@@ -0,0 +1,74 @@
+//! Labels derived from the credentials of the process at the other end of a connection.
+
+use interprocess::local_socket::PeerCreds;
+use std::collections::BTreeMap;
+
+/// A label the collector can attach to every metric received on a connection,
+/// identifying the process that sent it.
+///
+/// Values come from the peer credentials reported by the kernel when the connection is accepted,
+/// so clients cannot spoof them. A label set by the client with the same name is overwritten.
+/// See [`IPCCollector::peer_labels`](crate::IPCCollector::peer_labels).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerLabel {
+    /// The process id of the peer, as `pid`.
+    Pid,
+    /// The effective user id of the peer, as `uid`.
+    Uid,
+    /// The effective group id of the peer, as `gid`.
+    Gid,
+    /// The executable name of the peer, as `exe`, read from `/proc/<pid>`.
+    ///
+    /// Only available on Linux.
+    Exe,
+}
+
+impl PeerLabel {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Pid => "pid",
+            Self::Uid => "uid",
+            Self::Gid => "gid",
+            Self::Exe => "exe",
+        }
+    }
+
+    fn value(self, creds: &PeerCreds) -> Option<String> {
+        match self {
+            Self::Pid => creds.pid().map(|pid| pid.to_string()),
+            Self::Uid => creds.euid().map(|uid| uid.to_string()),
+            Self::Gid => creds.egid().map(|gid| gid.to_string()),
+            Self::Exe => creds.pid().and_then(executable_name),
+        }
+    }
+}
+
+/// Reads the executable name of `pid`, falling back to its command name when the executable link
+/// cannot be read, e.g. because the process belongs to another user.
+#[cfg(target_os = "linux")]
+fn executable_name(pid: libc::pid_t) -> Option<String> {
+    std::fs::read_link(format!("/proc/{pid}/exe"))
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .or_else(|| {
+            std::fs::read_to_string(format!("/proc/{pid}/comm"))
+                .ok()
+                .map(|comm| comm.trim_end().to_string())
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn executable_name(_pid: libc::pid_t) -> Option<String> {
+    None
+}
+
+/// Builds the labels to attach to metrics from a peer, skipping any that are unavailable.
+pub fn peer_labels(labels: &[PeerLabel], creds: &PeerCreds) -> BTreeMap<String, String> {
+    labels
+        .iter()
+        .filter_map(|label| Some((label.name().to_string(), label.value(creds)?)))
+        .collect()
+}
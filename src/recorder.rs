@@ -1,33 +1,65 @@
 use crate::{
     error::MetricsError,
-    events::{MetricData, MetricEvent, MetricKind, MetricMetadata, MetricOperation},
+    events::{ClientHello, MetricData, MetricEvent, MetricKind, MetricMetadata, MetricOperation},
+    framing,
+    socket::SocketPath,
 };
-use interprocess::local_socket::{GenericFilePath, GenericNamespaced, prelude::*};
+use interprocess::local_socket::prelude::*;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
 use std::{
     collections::BTreeMap,
-    io::Write,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    path::PathBuf,
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
-fn write_event(
-    stream: &Arc<Mutex<LocalSocketStream>>,
-    event: MetricEvent,
-) -> Result<(), MetricsError> {
+/// The connection to the collector, over a local socket, TCP or a datagram socket.
+#[derive(Debug)]
+enum Stream {
+    Local(LocalSocketStream),
+    Tcp(TcpStream),
+    /// A datagram socket, either connected or sending every datagram to the path, which keeps
+    /// working after the collector restarts and binds the path again.
+    #[cfg(unix)]
+    Datagram(UnixDatagram, Option<PathBuf>),
+}
+
+impl Stream {
+    /// Sends one encoded event: framed on streams, as a datagram of its own otherwise.
+    fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Local(stream) => write_frame(stream, payload),
+            Self::Tcp(stream) => write_frame(stream, payload),
+            #[cfg(unix)]
+            Self::Datagram(socket, None) => socket.send(payload).map(drop),
+            #[cfg(unix)]
+            Self::Datagram(socket, Some(path)) => socket.send_to(payload, path).map(drop),
+        }
+    }
+}
+
+fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&framing::encode(payload))?;
+    stream.flush()
+}
+
+fn write_event(stream: &Arc<Mutex<Stream>>, event: MetricEvent) -> Result<(), MetricsError> {
     let bytes: Vec<u8> = event.try_into()?;
-    let mut stream = stream.lock().unwrap();
-    stream.write_all(&bytes)?;
-    stream.write_all(b"\n")?;
-    stream.flush().map_err(Into::into)
+    stream.lock().unwrap().send(&bytes).map_err(Into::into)
 }
 
 #[derive(Debug)]
 struct Handle {
     key: metrics::Key,
-    stream: Arc<Mutex<LocalSocketStream>>,
+    stream: Arc<Mutex<Stream>>,
 }
 
 impl Handle {
-    const fn new(key: metrics::Key, stream: Arc<Mutex<LocalSocketStream>>) -> Self {
+    const fn new(key: metrics::Key, stream: Arc<Mutex<Stream>>) -> Self {
         Self { key, stream }
     }
 
@@ -81,10 +113,11 @@ impl metrics::HistogramFn for Handle {
 /// # Usage
 /// Typically, you do not construct an `IPCRecorder` directly. Instead, use [`IPCRecorderBuilder`](crate::recorder::IPCRecorderBuilder) to configure and install the recorder globally:
 ///
-/// ```rust
+/// ```rust,no_run
 /// use metrics_ipc_collector::IPCRecorderBuilder;
 /// let builder = IPCRecorderBuilder::default().socket("my_metrics.sock");
 /// builder.build()?;
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
 /// ```
 ///
 /// # See Also
@@ -93,7 +126,7 @@ impl metrics::HistogramFn for Handle {
 ///
 #[derive(Debug, Clone)]
 pub struct IPCRecorder {
-    stream: Arc<Mutex<LocalSocketStream>>,
+    stream: Arc<Mutex<Stream>>,
 }
 
 impl IPCRecorder {
@@ -105,18 +138,92 @@ impl IPCRecorder {
     /// * `stream` - The local socket stream to send metric events to.
     ///
     /// # Example
-    /// ```rust
+    /// ```rust,no_run
     /// # use metrics_ipc_collector::IPCRecorder;
-    /// # use interprocess::local_socket::LocalSocketStream;
-    /// # let stream = LocalSocketStream::connect("/tmp/my_metrics.sock").unwrap();
+    /// # use interprocess::local_socket::{GenericFilePath, prelude::*};
+    /// # let name = "/tmp/my_metrics.sock".to_fs_name::<GenericFilePath>().unwrap();
+    /// # let stream = LocalSocketStream::connect(name).unwrap();
     /// let recorder = IPCRecorder::new(stream);
     /// ```
+    #[must_use]
     pub fn new(stream: LocalSocketStream) -> Self {
         Self {
-            stream: Arc::new(Mutex::new(stream)),
+            stream: Arc::new(Mutex::new(Stream::Local(stream))),
+        }
+    }
+
+    /// Creates a new `IPCRecorder` from a TCP stream, for a collector listening with
+    /// [`IPCCollector::tcp`](crate::collector::IPCCollector::tcp).
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use metrics_ipc_collector::IPCRecorder;
+    /// use std::net::TcpStream;
+    /// let stream = TcpStream::connect("127.0.0.1:9091")?;
+    /// let recorder = IPCRecorder::from_tcp(stream);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[must_use]
+    pub fn from_tcp(stream: TcpStream) -> Self {
+        Self {
+            stream: Arc::new(Mutex::new(Stream::Tcp(stream))),
+        }
+    }
+
+    /// Creates a new `IPCRecorder` sending each event as a datagram on a connected Unix datagram
+    /// socket, for a collector listening with
+    /// [`IPCCollector::datagram`](crate::collector::IPCCollector::datagram).
+    ///
+    /// Set the socket to nonblocking to drop events rather than wait when the collector falls
+    /// behind. A connected socket stays tied to the socket it connected to, so nothing more is
+    /// received once the collector restarts; [`IPCRecorderBuilder::datagram`] sends to the path
+    /// instead.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use metrics_ipc_collector::IPCRecorder;
+    /// use std::os::unix::net::UnixDatagram;
+    /// let socket = UnixDatagram::unbound()?;
+    /// socket.connect("/run/metrics/collector.dgram")?;
+    /// let recorder = IPCRecorder::from_datagram(socket);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub fn from_datagram(socket: UnixDatagram) -> Self {
+        Self {
+            stream: Arc::new(Mutex::new(Stream::Datagram(socket, None))),
         }
     }
 
+    /// Identifies this client to the collector as `client_id`.
+    ///
+    /// The collector keys its per-source state, such as merged gauges and absolute counter
+    /// totals, by the client id instead of the connection, so a process that reconnects with the
+    /// same id resumes where it left off. Call this before recording any metrics.
+    ///
+    /// # Errors
+    /// Returns an error if the identification cannot be sent to the collector.
+    pub fn identify(&self, client_id: &str) -> Result<(), MetricsError> {
+        let hello = ClientHello {
+            client_id: client_id.to_string(),
+        };
+        write_event(&self.stream, MetricEvent::Hello(hello))
+    }
+
+    /// Tells the collector this client is still alive, without recording anything.
+    ///
+    /// Keeps a quiet connection from being closed by the collector's
+    /// [idle timeout](crate::collector::IPCCollector::idle_timeout). See
+    /// [`IPCRecorderBuilder::heartbeat`](crate::recorder::IPCRecorderBuilder::heartbeat) to send
+    /// heartbeats periodically.
+    ///
+    /// # Errors
+    /// Returns an error if the heartbeat cannot be sent to the collector.
+    pub fn heartbeat(&self) -> Result<(), MetricsError> {
+        write_event(&self.stream, MetricEvent::Heartbeat)
+    }
+
     fn register_metric(
         &self,
         key_name: &metrics::KeyName,
@@ -188,21 +295,34 @@ impl metrics::Recorder for IPCRecorder {
 /// Use this builder to set the socket path and install the recorder globally.
 ///
 /// # Example
-/// ```rust
+/// ```rust,no_run
 /// use metrics_ipc_collector::IPCRecorderBuilder;
 /// let builder = IPCRecorderBuilder::default().socket("my_metrics.sock");
 /// builder.build()?;
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
 /// ```
 ///
 #[derive(Debug)]
 pub struct IPCRecorderBuilder {
-    socket_path: String,
+    socket_path: SocketPath,
+    tcp_port: Option<u16>,
+    tcp_address: IpAddr,
+    #[cfg(unix)]
+    datagram: Option<PathBuf>,
+    client_id: Option<String>,
+    heartbeat: Option<Duration>,
 }
 
 impl Default for IPCRecorderBuilder {
     fn default() -> Self {
         Self {
-            socket_path: "metrics_collector.sock".into(),
+            socket_path: SocketPath::default(),
+            tcp_port: None,
+            tcp_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            #[cfg(unix)]
+            datagram: None,
+            client_id: None,
+            heartbeat: None,
         }
     }
 }
@@ -220,35 +340,162 @@ impl IPCRecorderBuilder {
     /// ```
     #[must_use]
     pub fn socket(mut self, socket_path: &str) -> Self {
-        self.socket_path = socket_path.to_string();
+        self.socket_path = SocketPath::Name(socket_path.to_string());
+        self
+    }
+
+    /// Connects to a collector
+    /// [listening on a socket file](crate::collector::IPCCollector::socket_file) at `path`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCRecorderBuilder;
+    /// let builder = IPCRecorderBuilder::default().socket_file("/run/metrics/collector.sock");
+    /// ```
+    #[must_use]
+    pub fn socket_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket_path = SocketPath::File(path.into());
+        self
+    }
+
+    /// Connects to a collector [listening on a TCP port](crate::collector::IPCCollector::tcp)
+    /// instead of a local socket.
+    ///
+    /// The collector is expected on 127.0.0.1 unless changed with
+    /// [`tcp_address`](Self::tcp_address).
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCRecorderBuilder;
+    /// let builder = IPCRecorderBuilder::default().tcp(9091);
+    /// ```
+    #[must_use]
+    pub const fn tcp(mut self, port: u16) -> Self {
+        self.tcp_port = Some(port);
+        self
+    }
+
+    /// Sets the address of the collector when connecting over [TCP](Self::tcp), 127.0.0.1 by
+    /// default.
+    #[must_use]
+    pub const fn tcp_address(mut self, address: IpAddr) -> Self {
+        self.tcp_address = address;
+        self
+    }
+
+    /// Sends every event as a datagram to a collector
+    /// [listening on a datagram socket](crate::collector::IPCCollector::datagram) at `path`.
+    ///
+    /// Recording a metric never blocks and no connection is held open: events the collector
+    /// cannot take straight away, or sent while it is not running, are dropped. Each datagram is
+    /// addressed to `path`, so events reach the collector again once it restarts. The
+    /// [client id](Self::client_id) is not sent, as every sender shares the collector's datagram
+    /// connection.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCRecorderBuilder;
+    /// let builder = IPCRecorderBuilder::default().datagram("/run/metrics/collector.dgram");
+    /// ```
+    #[cfg(unix)]
+    #[must_use]
+    pub fn datagram(mut self, path: impl Into<PathBuf>) -> Self {
+        self.datagram = Some(path.into());
+        self
+    }
+
+    /// Sets the client id sent to the collector when connecting.
+    ///
+    /// Use a name that is stable across restarts of the process, such as a worker or instance
+    /// name, so the collector recognises it when it reconnects. See
+    /// [`IPCRecorder::identify`](crate::recorder::IPCRecorder::identify).
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCRecorderBuilder;
+    /// let builder = IPCRecorderBuilder::default().client_id("worker-1");
+    /// ```
+    #[must_use]
+    pub fn client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string());
+        self
+    }
+
+    /// Sends a [heartbeat](crate::recorder::IPCRecorder::heartbeat) to the collector every
+    /// `interval` from a background thread.
+    ///
+    /// Set this shorter than the collector's
+    /// [idle timeout](crate::collector::IPCCollector::idle_timeout) so the connection stays open
+    /// while the process has nothing to record. The heartbeats come from their own thread, so
+    /// they keep the connection open as long as the process runs, even if the rest of it hangs;
+    /// only a stopped or exited process is timed out. Heartbeats are not sent over
+    /// [datagrams](Self::datagram), which hold no connection.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::IPCRecorderBuilder;
+    /// use std::time::Duration;
+    /// let builder = IPCRecorderBuilder::default().heartbeat(Duration::from_secs(15));
+    /// ```
+    #[must_use]
+    pub const fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
         self
     }
 
     /// Builds the IPC recorder and sets it as the global recorder.
     ///
-    /// This function connects to the IPC socket specified by `socket_path` and sets up the recorder.
+    /// This function connects to the IPC socket specified by `socket_path`, or the datagram
+    /// socket or TCP port if one is set, and sets up the recorder.
     /// All metrics recorded after this call will be sent to the IPC socket.
     ///
     /// # Example
-    /// ```rust
+    /// ```rust,no_run
     /// use metrics_ipc_collector::IPCRecorderBuilder;
     /// let builder = IPCRecorderBuilder::default().socket("my_metrics.sock");
     /// builder.build()?;
+    /// # Ok::<(), metrics_ipc_collector::MetricsError>(())
     /// ```
     ///
     /// # Errors
     /// Returns an error if the IPC connection cannot be established or if the recorder cannot be set.
     pub fn build(self) -> Result<(), MetricsError> {
-        let socket_name = if GenericNamespaced::is_supported() {
-            self.socket_path.to_ns_name::<GenericNamespaced>()?
+        #[cfg(unix)]
+        if let Some(path) = &self.datagram {
+            let socket = UnixDatagram::unbound()?;
+            socket.set_nonblocking(true)?;
+            let recorder = IPCRecorder {
+                stream: Arc::new(Mutex::new(Stream::Datagram(socket, Some(path.clone())))),
+            };
+            return metrics::set_global_recorder(recorder).map_err(Into::into);
+        }
+        let recorder = if let Some(port) = self.tcp_port {
+            let stream = TcpStream::connect(SocketAddr::new(self.tcp_address, port))?;
+            // Frames are small and written one at a time, so don't hold them back.
+            stream.set_nodelay(true)?;
+            stream.set_nonblocking(true)?;
+            IPCRecorder::from_tcp(stream)
         } else {
-            let socket_path = self.socket_path;
-            format!("/tmp/{socket_path}").to_fs_name::<GenericFilePath>()?
+            let stream = LocalSocketStream::connect(self.socket_path.name()?)?;
+            stream.set_nonblocking(true)?;
+            IPCRecorder::new(stream)
         };
-
-        let stream = LocalSocketStream::connect(socket_name)?;
-        stream.set_nonblocking(true)?;
-        let recorder = IPCRecorder::new(stream);
-        metrics::set_global_recorder(recorder).map_err(Into::into)
+        if let Some(client_id) = &self.client_id {
+            recorder.identify(client_id)?;
+        }
+        let heartbeats = self.heartbeat.map(|interval| (interval, recorder.clone()));
+        metrics::set_global_recorder(recorder)?;
+        // Only once the recorder is installed, so a failed build leaves no thread behind.
+        if let Some((interval, heartbeats)) = heartbeats {
+            thread::Builder::new()
+                .name("metrics-ipc-heartbeat".into())
+                .spawn(move || {
+                    loop {
+                        thread::sleep(interval);
+                        let _ = heartbeats.heartbeat();
+                    }
+                })?;
+        }
+        Ok(())
     }
 }
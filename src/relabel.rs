@@ -0,0 +1,475 @@
+//! Relabeling of metrics as they pass through the collector.
+//!
+//! Rules follow the semantics of Prometheus `relabel_configs`. The metric name is exposed to
+//! rules as the `__name__` label, so renaming a metric is a `replace` targeting `__name__`.
+
+use crate::{
+    error::MetricsError,
+    events::{MetricData, MetricMetadata},
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+/// The label holding the metric name while rules are applied.
+const NAME_LABEL: &str = "__name__";
+
+/// What a [`RelabelRule`] does when it is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelabelAction {
+    /// Sets `target_label` to `replacement`, expanded with the groups captured by matching the
+    /// regex against the source labels. Nothing happens if the regex does not match, and the
+    /// label is removed if the expanded value is empty. This is the default.
+    #[default]
+    Replace,
+    /// Drops the event unless the regex matches the source labels.
+    Keep,
+    /// Drops the event if the regex matches the source labels.
+    Drop,
+    /// Copies every label whose name matches the regex to a label named `replacement`,
+    /// expanded with the groups captured from the name.
+    LabelMap,
+    /// Removes every label whose name matches the regex.
+    LabelDrop,
+    /// Removes every label whose name does not match the regex.
+    LabelKeep,
+}
+
+/// A single relabeling step, applied to every metric received by the collector.
+///
+/// The regex must match the whole value. Source label values are joined with the separator, `;`
+/// by default, and labels that are not set count as empty. The label actions never touch
+/// `__name__`.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{IPCCollector, RelabelAction, RelabelRule};
+/// let collector = IPCCollector::default()
+///     // Rename `http_*` metrics to `web_*`.
+///     .relabel(
+///         RelabelRule::new(RelabelAction::Replace, "http_(.*)")?
+///             .source_labels(&["__name__"])
+///             .target_label("__name__")
+///             .replacement("web_$1"),
+///     )
+///     // Drop the `request_id` label from everything.
+///     .relabel(RelabelRule::new(RelabelAction::LabelDrop, "request_id")?);
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RuleConfig")]
+pub struct RelabelRule {
+    action: RelabelAction,
+    regex: Regex,
+    source_labels: Vec<String>,
+    separator: String,
+    target_label: Option<String>,
+    replacement: String,
+}
+
+impl RelabelRule {
+    /// Creates a rule performing `action` with `regex`, which is anchored at both ends.
+    ///
+    /// # Errors
+    /// Returns an error if `regex` is not a valid regular expression.
+    pub fn new(action: RelabelAction, regex: &str) -> Result<Self, MetricsError> {
+        Ok(Self {
+            action,
+            regex: Regex::new(&format!("^(?:{regex})$"))?,
+            source_labels: Vec::new(),
+            separator: ";".to_string(),
+            target_label: None,
+            replacement: "$1".to_string(),
+        })
+    }
+
+    /// Sets the labels whose values are joined and matched against the regex.
+    #[must_use]
+    pub fn source_labels(mut self, labels: &[&str]) -> Self {
+        self.source_labels = labels.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Sets the string placed between source label values, `;` by default.
+    #[must_use]
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Sets the label written by [`RelabelAction::Replace`].
+    #[must_use]
+    pub fn target_label(mut self, label: &str) -> Self {
+        self.target_label = Some(label.to_string());
+        self
+    }
+
+    /// Sets the value written by [`RelabelAction::Replace`] and the label name written by
+    /// [`RelabelAction::LabelMap`], `$1` by default. `$n` and `${name}` refer to capture groups.
+    #[must_use]
+    pub fn replacement(mut self, replacement: &str) -> Self {
+        self.replacement = replacement.to_string();
+        self
+    }
+
+    /// Applies the rule to a set of labels, returning `false` if the event should be dropped.
+    fn apply(&self, labels: &mut BTreeMap<String, String>) -> bool {
+        match self.action {
+            RelabelAction::Replace => {
+                let Some(target) = &self.target_label else {
+                    return true;
+                };
+                let value = self.source_value(labels);
+                if let Some(captures) = self.regex.captures(&value) {
+                    let mut replaced = String::new();
+                    captures.expand(&self.replacement, &mut replaced);
+                    if replaced.is_empty() {
+                        labels.remove(target);
+                    } else {
+                        labels.insert(target.clone(), replaced);
+                    }
+                }
+                true
+            }
+            RelabelAction::Keep => self.regex.is_match(&self.source_value(labels)),
+            RelabelAction::Drop => !self.regex.is_match(&self.source_value(labels)),
+            RelabelAction::LabelMap => {
+                let mapped: Vec<(String, String)> = labels
+                    .iter()
+                    .filter(|(name, _)| *name != NAME_LABEL)
+                    .filter_map(|(name, value)| {
+                        let captures = self.regex.captures(name)?;
+                        let mut mapped = String::new();
+                        captures.expand(&self.replacement, &mut mapped);
+                        Some((mapped, value.clone()))
+                    })
+                    .collect();
+                labels.extend(mapped);
+                true
+            }
+            RelabelAction::LabelDrop => {
+                labels.retain(|name, _| name == NAME_LABEL || !self.regex.is_match(name));
+                true
+            }
+            RelabelAction::LabelKeep => {
+                labels.retain(|name, _| name == NAME_LABEL || self.regex.is_match(name));
+                true
+            }
+        }
+    }
+
+    fn source_value(&self, labels: &BTreeMap<String, String>) -> String {
+        self.source_labels
+            .iter()
+            .map(|label| labels.get(label).map_or("", String::as_str))
+            .collect::<Vec<_>>()
+            .join(&self.separator)
+    }
+
+    /// Returns `true` if the rule only looks at the metric name, so it can be applied to metadata.
+    fn applies_to_metadata(&self) -> bool {
+        matches!(
+            self.action,
+            RelabelAction::Replace | RelabelAction::Keep | RelabelAction::Drop
+        ) && self.source_labels.iter().all(|label| label == NAME_LABEL)
+            && self
+                .target_label
+                .as_deref()
+                .is_none_or(|label| label == NAME_LABEL)
+    }
+}
+
+/// A [`RelabelRule`] as written in a configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    #[serde(default)]
+    action: RelabelAction,
+    #[serde(default = "RuleConfig::default_regex")]
+    regex: String,
+    #[serde(default)]
+    source_labels: Vec<String>,
+    separator: Option<String>,
+    target_label: Option<String>,
+    replacement: Option<String>,
+}
+
+impl RuleConfig {
+    fn default_regex() -> String {
+        "(.*)".to_string()
+    }
+}
+
+impl TryFrom<RuleConfig> for RelabelRule {
+    type Error = MetricsError;
+
+    fn try_from(config: RuleConfig) -> Result<Self, Self::Error> {
+        let mut rule = Self::new(config.action, &config.regex)?;
+        rule.source_labels = config.source_labels;
+        rule.target_label = config.target_label;
+        if let Some(separator) = config.separator {
+            rule.separator = separator;
+        }
+        if let Some(replacement) = config.replacement {
+            rule.replacement = replacement;
+        }
+        Ok(rule)
+    }
+}
+
+/// An ordered list of [`RelabelRule`]s, applied one after another.
+///
+/// Rules can be loaded from a TOML file with a `[[rules]]` table per rule, using the same field
+/// names as Prometheus:
+///
+/// ```toml
+/// [[rules]]
+/// source_labels = ["__name__"]
+/// regex = "http_(.*)"
+/// target_label = "__name__"
+/// replacement = "web_$1"
+///
+/// [[rules]]
+/// action = "labeldrop"
+/// regex = "request_id"
+/// ```
+///
+/// # Example
+/// ```rust,no_run
+/// use metrics_ipc_collector::{IPCCollector, RelabelRules};
+/// let rules = RelabelRules::from_file("/etc/metrics/relabel.toml")?;
+/// let collector = IPCCollector::default().relabel_rules(rules);
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelabelRules {
+    #[serde(default)]
+    rules: Vec<RelabelRule>,
+}
+
+impl RelabelRules {
+    /// Reads rules from a TOML file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, is not valid TOML, or contains an invalid rule.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MetricsError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Appends a rule, applied after every rule already in the list.
+    pub fn push(&mut self, rule: RelabelRule) {
+        self.rules.push(rule);
+    }
+
+    /// Appends every rule in `rules`.
+    pub fn extend(&mut self, rules: Self) {
+        self.rules.extend(rules.rules);
+    }
+
+    /// Applies every rule to a metric, returning `None` if it should be dropped.
+    #[must_use]
+    pub fn relabel_metric(&self, mut metric: MetricData) -> Option<MetricData> {
+        if self.rules.is_empty() {
+            return Some(metric);
+        }
+        metric.labels.insert(NAME_LABEL.to_string(), metric.name);
+        for rule in &self.rules {
+            if !rule.apply(&mut metric.labels) {
+                return None;
+            }
+        }
+        metric.name = take_name(&mut metric.labels)?;
+        Some(metric)
+    }
+
+    /// Applies the rules that only look at the metric name to metadata, returning `None` if it
+    /// should be dropped. Metadata has no labels, so every other rule is skipped.
+    #[must_use]
+    pub fn relabel_metadata(&self, mut metadata: MetricMetadata) -> Option<MetricMetadata> {
+        if self.rules.is_empty() {
+            return Some(metadata);
+        }
+        let mut labels = BTreeMap::from([(NAME_LABEL.to_string(), metadata.name)]);
+        for rule in self.rules.iter().filter(|rule| rule.applies_to_metadata()) {
+            if !rule.apply(&mut labels) {
+                return None;
+            }
+        }
+        metadata.name = take_name(&mut labels)?;
+        Some(metadata)
+    }
+}
+
+impl FromStr for RelabelRules {
+    type Err = MetricsError;
+
+    /// Parses rules from a TOML document.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+/// Removes the metric name from the labels, returning `None` if a rule removed it.
+fn take_name(labels: &mut BTreeMap<String, String>) -> Option<String> {
+    let name = labels.remove(NAME_LABEL);
+    if name.is_none() {
+        log::debug!("Relabeling removed the metric name, dropping the event");
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{MetricKind, MetricOperation};
+
+    fn metric(name: &str, labels: &[(&str, &str)]) -> MetricData {
+        MetricData {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect(),
+            operation: MetricOperation::IncrementCounter(1),
+        }
+    }
+
+    fn rules(rules: impl IntoIterator<Item = RelabelRule>) -> RelabelRules {
+        let mut list = RelabelRules::default();
+        for rule in rules {
+            list.push(rule);
+        }
+        list
+    }
+
+    #[test]
+    fn renames_and_rewrites_labels() {
+        let rules = rules([
+            RelabelRule::new(RelabelAction::Replace, "http_(.*)")
+                .unwrap()
+                .source_labels(&["__name__"])
+                .target_label("__name__")
+                .replacement("web_$1"),
+            RelabelRule::new(RelabelAction::Replace, "(.*);(.*)")
+                .unwrap()
+                .source_labels(&["method", "status"])
+                .target_label("route")
+                .replacement("$1 $2"),
+            RelabelRule::new(RelabelAction::LabelDrop, "status").unwrap(),
+        ]);
+
+        let metric = rules
+            .relabel_metric(metric(
+                "http_requests",
+                &[("method", "GET"), ("status", "200")],
+            ))
+            .unwrap();
+        assert_eq!(metric.name, "web_requests");
+        assert_eq!(metric.labels.len(), 2);
+        assert_eq!(metric.labels["method"], "GET");
+        assert_eq!(metric.labels["route"], "GET 200");
+    }
+
+    #[test]
+    fn keeps_and_drops_events() {
+        let keep = rules([RelabelRule::new(RelabelAction::Keep, "app_.*")
+            .unwrap()
+            .source_labels(&["__name__"])]);
+        assert!(keep.relabel_metric(metric("app_requests", &[])).is_some());
+        assert!(keep.relabel_metric(metric("tokio_tasks", &[])).is_none());
+
+        let drop = rules([RelabelRule::new(RelabelAction::Drop, "debug")
+            .unwrap()
+            .source_labels(&["level"])]);
+        assert!(
+            drop.relabel_metric(metric("logs", &[("level", "debug")]))
+                .is_none()
+        );
+        assert!(
+            drop.relabel_metric(metric("logs", &[("level", "info")]))
+                .is_some()
+        );
+        // Unset labels count as empty.
+        assert!(drop.relabel_metric(metric("logs", &[])).is_some());
+    }
+
+    #[test]
+    fn maps_and_keeps_labels_without_touching_the_name() {
+        let rules = rules([
+            RelabelRule::new(RelabelAction::LabelMap, "k8s_(.*)")
+                .unwrap()
+                .replacement("$1"),
+            RelabelRule::new(RelabelAction::LabelKeep, "pod|namespace").unwrap(),
+        ]);
+        let metric = rules
+            .relabel_metric(metric(
+                "requests",
+                &[
+                    ("k8s_pod", "web-0"),
+                    ("k8s_namespace", "prod"),
+                    ("host", "a"),
+                ],
+            ))
+            .unwrap();
+        assert_eq!(metric.name, "requests");
+        assert_eq!(
+            metric.labels,
+            BTreeMap::from([
+                ("namespace".to_string(), "prod".to_string()),
+                ("pod".to_string(), "web-0".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn drops_events_whose_name_is_removed() {
+        let rules = rules([RelabelRule::new(RelabelAction::Replace, ".*")
+            .unwrap()
+            .source_labels(&["missing"])
+            .target_label("__name__")
+            .replacement("")]);
+        assert!(rules.relabel_metric(metric("requests", &[])).is_none());
+    }
+
+    #[test]
+    fn applies_name_rules_to_metadata() {
+        let rules: RelabelRules = r#"
+            [[rules]]
+            source_labels = ["__name__"]
+            regex = "http_(.*)"
+            target_label = "__name__"
+            replacement = "web_$1"
+
+            [[rules]]
+            source_labels = ["method"]
+            action = "drop"
+            regex = ""
+        "#
+        .parse()
+        .unwrap();
+
+        let metadata = rules
+            .relabel_metadata(MetricMetadata {
+                name: "http_requests".to_string(),
+                kind: MetricKind::Counter,
+                description: String::new(),
+                unit: None,
+            })
+            .unwrap();
+        // The label based drop rule would drop everything without a method, but is skipped.
+        assert_eq!(metadata.name, "web_requests");
+    }
+
+    #[test]
+    fn rejects_invalid_rule_files() {
+        assert!("[[rules]]\nregex = \"(\"".parse::<RelabelRules>().is_err());
+        assert!("[[rules]]\nunknown = 1".parse::<RelabelRules>().is_err());
+        assert!(
+            "[[rules]]\naction = \"rename\""
+                .parse::<RelabelRules>()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,459 @@
+//! Forwarding of collected metrics to an upstream collector.
+//!
+//! A collector using a [`RelaySink`] acts as a client of another collector, so process trees can
+//! be aggregated hierarchically, e.g. a collector per container relaying to one per node.
+//!
+//! Events are queued and written upstream by a thread of their own, so a slow or unreachable
+//! upstream collector never blocks the collector feeding the sink.
+
+use crate::{
+    error::MetricsError,
+    events::{ClientHello, MetricData, MetricEvent, MetricKind, MetricMetadata, MetricOperation},
+    framing,
+    sink::MetricSink,
+    socket::SocketPath,
+};
+use interprocess::local_socket::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread,
+    time::Duration,
+};
+
+/// The number of events that may wait to be written upstream by default.
+const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// How long a write to the upstream collector may block before the connection is given up on.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The wait before the first attempt to reconnect, doubled after each failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest wait between attempts to reconnect.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The name and labels of a series waiting to be flushed.
+type PendingKey = (String, BTreeMap<String, String>);
+
+/// The combined effect of the operations applied to a series since the last flush.
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    Counter(u64),
+    AbsoluteCounter(u64),
+    /// A gauge that was set to `set`, if it was set at all, then moved by `delta`.
+    Gauge {
+        set: Option<f64>,
+        delta: f64,
+    },
+}
+
+impl Pending {
+    fn new(operation: &MetricOperation) -> Option<Self> {
+        Some(match *operation {
+            MetricOperation::IncrementCounter(value) => Self::Counter(value),
+            MetricOperation::SetCounter(value) => Self::AbsoluteCounter(value),
+            MetricOperation::IncrementGauge(value) => Self::Gauge {
+                set: None,
+                delta: value,
+            },
+            MetricOperation::DecrementGauge(value) => Self::Gauge {
+                set: None,
+                delta: -value,
+            },
+            MetricOperation::SetGauge(value) => Self::Gauge {
+                set: Some(value),
+                delta: 0.0,
+            },
+            MetricOperation::RecordHistogram(_) => return None,
+        })
+    }
+
+    /// Folds a later operation into this one, returning `false` if they cannot be combined.
+    fn combine(&mut self, operation: &MetricOperation) -> bool {
+        match (self, operation) {
+            (Self::Counter(total), &MetricOperation::IncrementCounter(value)) => {
+                *total = total.saturating_add(value);
+            }
+            (Self::AbsoluteCounter(total), &MetricOperation::SetCounter(value)) => *total = value,
+            (Self::Gauge { delta, .. }, &MetricOperation::IncrementGauge(value)) => *delta += value,
+            (Self::Gauge { delta, .. }, &MetricOperation::DecrementGauge(value)) => *delta -= value,
+            (Self::Gauge { set, delta }, &MetricOperation::SetGauge(value)) => {
+                *set = Some(value);
+                *delta = 0.0;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn operation(self) -> MetricOperation {
+        match self {
+            Self::Counter(total) => MetricOperation::IncrementCounter(total),
+            Self::AbsoluteCounter(total) => MetricOperation::SetCounter(total),
+            Self::Gauge {
+                set: Some(value),
+                delta,
+            } => MetricOperation::SetGauge(value + delta),
+            Self::Gauge { set: None, delta } => MetricOperation::IncrementGauge(delta),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Upstream {
+    /// Events waiting to be written by the [`Writer`].
+    queue: SyncSender<MetricEvent>,
+    /// Set while events are dropped because the queue is full, so it is only logged once.
+    dropping: AtomicBool,
+    /// Operations waiting for the next flush, when pre-aggregating.
+    pending: Mutex<HashMap<PendingKey, Pending>>,
+}
+
+impl Upstream {
+    fn send(&self, event: MetricEvent) {
+        match self.queue.try_send(event) {
+            Ok(()) => {
+                if self.dropping.swap(false, Ordering::Relaxed) {
+                    log::info!("Relaying events upstream again");
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if !self.dropping.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Relay queue is full, dropping events until the upstream catches up"
+                    );
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                log::warn!("Relay writer has stopped, dropping event");
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for ((name, labels), pending) in pending {
+            self.send(MetricEvent::Metric(MetricData {
+                name,
+                labels,
+                operation: pending.operation(),
+            }));
+        }
+    }
+}
+
+/// Writes queued events to the upstream collector, reconnecting whenever the connection fails.
+struct Writer {
+    socket_path: SocketPath,
+    client_id: Option<String>,
+    stream: Option<LocalSocketStream>,
+    /// Metadata relayed so far, sent again after reconnecting in case the upstream restarted.
+    metadata: HashMap<(MetricKind, String), MetricMetadata>,
+}
+
+impl Writer {
+    /// Writes events until the sink has been dropped and the queue drained.
+    fn run(mut self, queue: &Receiver<MetricEvent>) {
+        for event in queue {
+            if let MetricEvent::Metadata(metadata) = &event {
+                self.metadata
+                    .insert((metadata.kind, metadata.name.clone()), metadata.clone());
+            }
+            match Vec::try_from(event) {
+                Ok(payload) => self.write(&framing::encode(&payload)),
+                Err(e) => log::warn!("Failed to encode relayed event: {e}"),
+            }
+        }
+    }
+
+    /// Writes a frame, reconnecting until it has been written.
+    fn write(&mut self, frame: &[u8]) {
+        loop {
+            let mut stream = self.stream.take().unwrap_or_else(|| self.reconnect());
+            match stream.write_all(frame).and_then(|()| stream.flush()) {
+                Ok(()) => {
+                    self.stream = Some(stream);
+                    return;
+                }
+                Err(e) => log::warn!("Failed to relay event upstream, reconnecting: {e}"),
+            }
+        }
+    }
+
+    /// Connects to the upstream collector, backing off exponentially between failed attempts.
+    fn reconnect(&self) -> LocalSocketStream {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match self.connect() {
+                Ok(stream) => return stream,
+                Err(e) => {
+                    log::warn!(
+                        "Failed to connect to the upstream collector at {}, retrying in {backoff:?}: {e}",
+                        self.socket_path
+                    );
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Connects to the upstream collector, identifying the sink and describing every metric
+    /// relayed so far.
+    fn connect(&self) -> Result<LocalSocketStream, MetricsError> {
+        let mut stream = LocalSocketStream::connect(self.socket_path.name()?)?;
+        stream.set_send_timeout(Some(SEND_TIMEOUT))?;
+        let hello = self.client_id.iter().map(|client_id| {
+            MetricEvent::Hello(ClientHello {
+                client_id: client_id.clone(),
+            })
+        });
+        let metadata = self.metadata.values().cloned().map(MetricEvent::Metadata);
+        for event in hello.chain(metadata) {
+            let payload: Vec<u8> = event.try_into()?;
+            stream.write_all(&framing::encode(&payload))?;
+        }
+        stream.flush()?;
+        Ok(stream)
+    }
+}
+
+/// A sink forwarding every metric to another collector, as an
+/// [`IPCRecorder`](crate::IPCRecorder) would.
+///
+/// Build one with [`RelaySinkBuilder`]. Metadata and histogram samples are forwarded as they
+/// arrive. Counters and gauges are too, unless [pre-aggregation](RelaySinkBuilder::aggregate)
+/// is enabled, in which case the updates to each series are combined and forwarded periodically.
+///
+/// Events are queued and written upstream by a background thread. When the upstream connection
+/// fails, the thread reconnects with exponential backoff, describes every metric relayed so far
+/// again, and carries on with the event that failed. Events arriving while the
+/// [queue](RelaySinkBuilder::queue_capacity) is full are dropped, and a warning is logged.
+///
+/// Clients have no way to remove series, so gauges expired by the
+/// [`DisconnectPolicy`](crate::DisconnectPolicy) are set to zero upstream instead, as the
+/// [recorder sinks](crate::RecorderSink) do, and expired counters keep their last value. Updates
+/// still waiting to be aggregated are forwarded before a counter expires.
+#[derive(Debug, Clone)]
+pub struct RelaySink {
+    upstream: Arc<Upstream>,
+    hop_labels: BTreeMap<String, String>,
+    aggregate: bool,
+}
+
+impl MetricSink for RelaySink {
+    fn handle_metric(&self, mut metric: MetricData) {
+        metric.labels.extend(self.hop_labels.clone());
+        let aggregated = self
+            .aggregate
+            .then(|| Pending::new(&metric.operation))
+            .flatten();
+        let Some(new) = aggregated else {
+            self.upstream.send(MetricEvent::Metric(metric));
+            return;
+        };
+
+        let key = (metric.name, metric.labels);
+        let mut pending = self.upstream.pending.lock().unwrap();
+        match pending.get_mut(&key) {
+            Some(existing) => {
+                if existing.combine(&metric.operation) {
+                    return;
+                }
+                // A counter switching between increments and absolute values: send what came first.
+                let older = std::mem::replace(existing, new);
+                drop(pending);
+                let (name, labels) = key;
+                self.upstream.send(MetricEvent::Metric(MetricData {
+                    name,
+                    labels,
+                    operation: older.operation(),
+                }));
+            }
+            None => {
+                pending.insert(key, new);
+            }
+        }
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        self.upstream.send(MetricEvent::Metadata(metadata));
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        let mut labels = labels.clone();
+        labels.extend(self.hop_labels.clone());
+        let zeroed = Pending::Gauge {
+            set: Some(0.0),
+            delta: 0.0,
+        };
+        let removed = if !self.aggregate {
+            (kind == MetricKind::Gauge).then_some(zeroed)
+        } else if kind == MetricKind::Gauge {
+            // Zeroed with the next flush, replacing whatever the gauge was waiting to be set to.
+            self.upstream
+                .pending
+                .lock()
+                .unwrap()
+                .insert((name.to_string(), labels), zeroed);
+            return;
+        } else {
+            self.upstream
+                .pending
+                .lock()
+                .unwrap()
+                .remove(&(name.to_string(), labels.clone()))
+        };
+        if let Some(removed) = removed {
+            self.upstream.send(MetricEvent::Metric(MetricData {
+                name: name.to_string(),
+                labels,
+                operation: removed.operation(),
+            }));
+        }
+    }
+}
+
+/// Builder for a [`RelaySink`].
+///
+/// # Example
+/// ```rust,no_run
+/// use metrics_ipc_collector::{IPCCollector, RelaySinkBuilder};
+/// use std::time::Duration;
+///
+/// let relay = RelaySinkBuilder::default()
+///     .socket_file("/run/metrics/node.sock")
+///     .client_id("container-a")
+///     .hop_label("container", "a")
+///     .aggregate(Duration::from_secs(1))
+///     .build()?;
+/// IPCCollector::default()
+///     .socket_file("/run/metrics/container.sock")
+///     .sink(relay)
+///     .start_collecting()?;
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct RelaySinkBuilder {
+    socket_path: SocketPath,
+    client_id: Option<String>,
+    hop_labels: BTreeMap<String, String>,
+    aggregate: Option<Duration>,
+    queue_capacity: Option<usize>,
+}
+
+impl RelaySinkBuilder {
+    /// Sets the name of the upstream collector's socket.
+    ///
+    /// See [`IPCCollector::socket`](crate::IPCCollector::socket).
+    #[must_use]
+    pub fn socket(mut self, socket_path: &str) -> Self {
+        self.socket_path = SocketPath::Name(socket_path.to_string());
+        self
+    }
+
+    /// Connects to an upstream collector listening on a socket file at `path`.
+    ///
+    /// See [`IPCCollector::socket_file`](crate::IPCCollector::socket_file).
+    #[must_use]
+    pub fn socket_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket_path = SocketPath::File(path.into());
+        self
+    }
+
+    /// Sets the client id sent to the upstream collector when connecting.
+    ///
+    /// See [`IPCRecorderBuilder::client_id`](crate::IPCRecorderBuilder::client_id).
+    #[must_use]
+    pub fn client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string());
+        self
+    }
+
+    /// Adds a label to every metric relayed, identifying the collector it passed through.
+    ///
+    /// A label with the same name set further down the tree is overwritten.
+    #[must_use]
+    pub fn hop_label(mut self, name: &str, value: &str) -> Self {
+        self.hop_labels.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Combines counter and gauge updates and forwards them once every `interval`.
+    ///
+    /// Increments to a counter are summed, and a gauge is forwarded as its net change, or its
+    /// final value if it was set, reducing the traffic to the upstream collector at the cost of
+    /// up to `interval` of delay.
+    #[must_use]
+    pub const fn aggregate(mut self, interval: Duration) -> Self {
+        self.aggregate = Some(interval);
+        self
+    }
+
+    /// Sets how many events may wait to be written upstream, 1024 by default.
+    ///
+    /// Events arriving while the queue is full, e.g. because the upstream collector is
+    /// unreachable, are dropped.
+    #[must_use]
+    pub const fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Connects to the upstream collector and builds the sink.
+    ///
+    /// Only the first connection must succeed. Later failures are retried in the background.
+    ///
+    /// # Errors
+    /// Returns an error if the upstream collector cannot be connected to.
+    pub fn build(self) -> Result<RelaySink, MetricsError> {
+        let mut writer = Writer {
+            socket_path: self.socket_path,
+            client_id: self.client_id,
+            stream: None,
+            metadata: HashMap::new(),
+        };
+        writer.stream = Some(writer.connect()?);
+        // A zero capacity would make every event a rendezvous, which `try_send` never completes.
+        let capacity = self.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY).max(1);
+        let (queue, events) = mpsc::sync_channel(capacity);
+        thread::Builder::new()
+            .name("metrics-relay".to_string())
+            .spawn(move || writer.run(&events))
+            .map_err(MetricsError::Io)?;
+
+        let upstream = Arc::new(Upstream {
+            queue,
+            dropping: AtomicBool::new(false),
+            pending: Mutex::new(HashMap::new()),
+        });
+        if let Some(interval) = self.aggregate {
+            flush_every(interval, Arc::downgrade(&upstream));
+        }
+        Ok(RelaySink {
+            upstream,
+            hop_labels: self.hop_labels,
+            aggregate: self.aggregate.is_some(),
+        })
+    }
+}
+
+/// Flushes pre-aggregated updates every `interval`, until the sink has been dropped.
+fn flush_every(interval: Duration, upstream: Weak<Upstream>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let Some(upstream) = upstream.upgrade() else {
+                return;
+            };
+            upstream.flush();
+        }
+    });
+}
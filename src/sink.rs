@@ -0,0 +1,202 @@
+//! Destinations for the metrics received by the collector.
+//!
+//! The [`IPCCollector`](crate::IPCCollector) hands every decoded event to a [`MetricSink`].
+//! By default this is [`GlobalRecorderSink`], which forwards to whatever global recorder is
+//! installed, but any [`metrics::Recorder`] can be targeted with [`RecorderSink`], or a custom
+//! store can implement the trait directly.
+
+use crate::events::{MetricData, MetricKind, MetricMetadata, MetricOperation};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Metadata attached to every metric registered by a sink, matching what the `metrics` macros use.
+static METADATA: metrics::Metadata<'static> =
+    metrics::Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
+
+/// A destination for metric events received by the [`IPCCollector`](crate::IPCCollector).
+///
+/// Implementations must be thread safe, as events from every connection are dispatched to the
+/// same sink concurrently.
+///
+/// # Example
+/// ```rust
+/// use metrics_ipc_collector::{IPCCollector, MetricData, MetricMetadata, MetricSink};
+///
+/// struct Logger;
+///
+/// impl MetricSink for Logger {
+///     fn handle_metric(&self, metric: MetricData) {
+///         println!("{} {:?}", metric.name, metric.operation);
+///     }
+///
+///     fn handle_metadata(&self, metadata: MetricMetadata) {
+///         println!("{} {}", metadata.name, metadata.description);
+///     }
+/// }
+///
+/// let collector = IPCCollector::default().sink(Logger);
+/// ```
+pub trait MetricSink: Send + Sync {
+    /// Applies a metric operation received from a client.
+    fn handle_metric(&self, metric: MetricData);
+
+    /// Records metadata describing a metric received from a client.
+    fn handle_metadata(&self, metadata: MetricMetadata);
+
+    /// Removes a series that is no longer reported by any connected client.
+    ///
+    /// Called by the collector when its [`DisconnectPolicy`](crate::DisconnectPolicy) expires a
+    /// series. The default implementation does nothing, so sinks that keep state should override
+    /// it.
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        let _ = (kind, name, labels);
+    }
+}
+
+impl<S: MetricSink + ?Sized> MetricSink for Arc<S> {
+    fn handle_metric(&self, metric: MetricData) {
+        (**self).handle_metric(metric);
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        (**self).handle_metadata(metadata);
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        (**self).remove_metric(kind, name, labels);
+    }
+}
+
+/// A sink that forwards metrics to the globally installed [`metrics::Recorder`].
+///
+/// This is the default sink of the [`IPCCollector`](crate::IPCCollector), so collected metrics are
+/// exported by whichever exporter has been installed with [`metrics::set_global_recorder`].
+///
+/// [`metrics::Recorder`]s have no way to forget a series, so removed gauges are set to zero
+/// instead, and removed counters and histograms keep their last value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalRecorderSink;
+
+impl MetricSink for GlobalRecorderSink {
+    fn handle_metric(&self, metric: MetricData) {
+        metrics::with_recorder(|recorder| record_metric(recorder, metric));
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        metrics::with_recorder(|recorder| describe_metric(recorder, metadata));
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        metrics::with_recorder(|recorder| remove_metric(recorder, kind, name, labels));
+    }
+}
+
+/// A sink that forwards metrics to a specific [`metrics::Recorder`] rather than the global one.
+///
+/// Like [`GlobalRecorderSink`], it sets removed gauges to zero, as a recorder cannot forget them.
+///
+/// # Example
+/// ```rust
+/// use metrics_exporter_prometheus::PrometheusBuilder;
+/// use metrics_ipc_collector::{IPCCollector, RecorderSink};
+///
+/// let recorder = PrometheusBuilder::new().build_recorder();
+/// let handle = recorder.handle();
+/// let collector = IPCCollector::default().sink(RecorderSink::new(recorder));
+/// // `handle.render()` now returns the collected metrics.
+/// ```
+#[derive(Debug)]
+pub struct RecorderSink<R> {
+    recorder: R,
+}
+
+impl<R: metrics::Recorder> RecorderSink<R> {
+    /// Creates a sink forwarding to `recorder`.
+    pub const fn new(recorder: R) -> Self {
+        Self { recorder }
+    }
+
+    /// Returns a reference to the wrapped recorder.
+    pub const fn recorder(&self) -> &R {
+        &self.recorder
+    }
+}
+
+impl<R: metrics::Recorder + Send + Sync> MetricSink for RecorderSink<R> {
+    fn handle_metric(&self, metric: MetricData) {
+        record_metric(&self.recorder, metric);
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        describe_metric(&self.recorder, metadata);
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        remove_metric(&self.recorder, kind, name, labels);
+    }
+}
+
+fn record_metric(recorder: &dyn metrics::Recorder, metric: MetricData) {
+    let labels: Vec<metrics::Label> = metric
+        .labels
+        .into_iter()
+        .map(|(k, v)| metrics::Label::new(k, v))
+        .collect();
+    let key = metrics::Key::from_parts(metric.name, labels);
+
+    match metric.operation {
+        MetricOperation::IncrementCounter(value) => {
+            recorder.register_counter(&key, &METADATA).increment(value);
+        }
+        MetricOperation::SetCounter(value) => {
+            recorder.register_counter(&key, &METADATA).absolute(value);
+        }
+        MetricOperation::IncrementGauge(value) => {
+            recorder.register_gauge(&key, &METADATA).increment(value);
+        }
+        MetricOperation::DecrementGauge(value) => {
+            recorder.register_gauge(&key, &METADATA).decrement(value);
+        }
+        MetricOperation::SetGauge(value) => {
+            recorder.register_gauge(&key, &METADATA).set(value);
+        }
+        MetricOperation::RecordHistogram(value) => {
+            recorder.register_histogram(&key, &METADATA).record(value);
+        }
+    }
+}
+
+/// Zeroes a removed gauge, the closest a recorder gets to forgetting it.
+fn remove_metric(
+    recorder: &dyn metrics::Recorder,
+    kind: MetricKind,
+    name: &str,
+    labels: &BTreeMap<String, String>,
+) {
+    if kind == MetricKind::Gauge {
+        record_metric(
+            recorder,
+            MetricData {
+                name: name.to_string(),
+                labels: labels.clone(),
+                operation: MetricOperation::SetGauge(0.0),
+            },
+        );
+    } else {
+        log::debug!("Recorders cannot remove {kind:?} {name}, keeping its last value");
+    }
+}
+
+fn describe_metric(recorder: &dyn metrics::Recorder, metadata: MetricMetadata) {
+    let unit = metadata
+        .unit
+        .as_deref()
+        .and_then(metrics::Unit::from_string);
+    let name = metrics::KeyName::from(metadata.name);
+    let description = metrics::SharedString::from(metadata.description);
+
+    match metadata.kind {
+        MetricKind::Counter => recorder.describe_counter(name, unit, description),
+        MetricKind::Gauge => recorder.describe_gauge(name, unit, description),
+        MetricKind::Histogram => recorder.describe_histogram(name, unit, description),
+    }
+}
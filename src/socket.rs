@@ -0,0 +1,186 @@
+//! Socket naming and file permission helpers shared by the collector and recorder.
+//!
+//! Socket names use the abstract namespace where the platform supports it and fall back to a
+//! socket file under `/tmp` otherwise. A socket file at a given path is requested explicitly,
+//! which is what allows permissions and ownership to be applied to it.
+
+use interprocess::local_socket::{
+    GenericFilePath, GenericNamespaced, Name, NameType, ToFsName, ToNsName,
+};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Where a local socket is bound or connected to.
+#[derive(Debug, Clone)]
+pub enum SocketPath {
+    /// A name in the abstract namespace, or a socket file under `/tmp` where namespaced sockets
+    /// are not supported.
+    Name(String),
+    /// A socket file at exactly this path.
+    File(PathBuf),
+}
+
+impl Default for SocketPath {
+    fn default() -> Self {
+        Self::Name("metrics_collector.sock".into())
+    }
+}
+
+impl fmt::Display for SocketPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => f.write_str(name),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl SocketPath {
+    /// Returns the socket file, or `None` for a namespaced socket.
+    pub fn file(&self) -> Option<PathBuf> {
+        match self {
+            Self::Name(_) if GenericNamespaced::is_supported() => None,
+            Self::Name(name) => Some(format!("/tmp/{name}").into()),
+            Self::File(path) => Some(path.clone()),
+        }
+    }
+
+    /// Resolves into the name used to bind or connect the local socket.
+    pub fn name(&self) -> io::Result<Name<'static>> {
+        self.file().map_or_else(
+            || self.to_string().to_ns_name::<GenericNamespaced>(),
+            ToFsName::to_fs_name::<GenericFilePath>,
+        )
+    }
+}
+
+/// Permissions and ownership applied to a socket file when the collector creates it.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketPermissions {
+    pub mode: Option<u32>,
+    pub group: Option<u32>,
+    pub dir_mode: Option<u32>,
+}
+
+#[cfg(unix)]
+impl SocketPermissions {
+    const fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.group.is_none() && self.dir_mode.is_none()
+    }
+
+    /// Creates the parent directory of `socket_file` if needed, applying the directory mode to
+    /// every directory created. Directories that already exist are left as they are.
+    pub fn prepare_dir(&self, socket_file: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (Some(dir_mode), Some(dir)) = (self.dir_mode, socket_file.parent()) else {
+            return Ok(());
+        };
+        let missing: Vec<_> = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .collect();
+        std::fs::create_dir_all(dir)?;
+        for created in missing {
+            std::fs::set_permissions(created, std::fs::Permissions::from_mode(dir_mode))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the socket file mode to the listener options so it is set before `bind()`.
+    pub fn listener_options<'n>(
+        &self,
+        options: interprocess::local_socket::ListenerOptions<'n>,
+    ) -> interprocess::local_socket::ListenerOptions<'n> {
+        use interprocess::os::unix::local_socket::ListenerOptionsExt;
+
+        match self.mode {
+            Some(mode) => options.mode(mode as libc::mode_t),
+            None => options,
+        }
+    }
+
+    /// Binds a Unix datagram socket at `socket_file`, with the socket file mode set before
+    /// `bind()` as [`listener_options`](Self::listener_options) does for stream sockets.
+    ///
+    /// On Linux the mode of an unbound socket is the mode its file is created with, so it is
+    /// never reachable with the permissions the umask would otherwise leave.
+    pub fn bind_datagram(
+        &self,
+        socket_file: &Path,
+    ) -> io::Result<std::os::unix::net::UnixDatagram> {
+        use std::os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        };
+
+        let Some(mode) = self.mode else {
+            return std::os::unix::net::UnixDatagram::bind(socket_file);
+        };
+        // SAFETY: an all-zero `sockaddr_un` is valid, with an empty, nul terminated path.
+        let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        let path = socket_file.as_os_str().as_bytes();
+        if path.len() >= addr.sun_path.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket path is too long",
+            ));
+        }
+        addr.sun_family = libc::sa_family_t::try_from(libc::AF_UNIX).map_err(io::Error::other)?;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+            *dst = libc::c_char::from_ne_bytes([*src]);
+        }
+        let addr_len = libc::socklen_t::try_from(std::mem::size_of::<libc::sockaddr_un>())
+            .map_err(io::Error::other)?;
+
+        // SAFETY: `socket` has no preconditions.
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just created and is owned by nothing else.
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: `socket` is open, and `addr` is a valid `sockaddr_un` of `addr_len` bytes.
+        let failed = unsafe {
+            libc::fcntl(socket.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) == -1
+                || libc::fchmod(socket.as_raw_fd(), mode as libc::mode_t) == -1
+                || libc::bind(
+                    socket.as_raw_fd(),
+                    std::ptr::from_ref(&addr).cast::<libc::sockaddr>(),
+                    addr_len,
+                ) == -1
+        };
+        if failed {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket.into())
+    }
+
+    /// Applies the owning group and the exact file mode once the socket file has been created.
+    ///
+    /// `bind()` still masks the mode set on the listener options with the umask, so the mode is
+    /// set again here now that nothing more permissive than requested can have been created.
+    pub fn apply_file(&self, socket_file: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(gid) = self.group {
+            std::os::unix::fs::chown(socket_file, None, Some(gid))?;
+        }
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(socket_file, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    /// Logs a warning if permissions were configured for a socket that has no file to apply them to.
+    pub fn warn_if_namespaced(&self, socket_path: &SocketPath) {
+        if !self.is_empty() && socket_path.file().is_none() {
+            log::warn!(
+                "socket permissions are ignored for namespaced socket {socket_path}, use a socket file instead"
+            );
+        }
+    }
+}
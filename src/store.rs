@@ -0,0 +1,269 @@
+//! An in-memory store of the collected metrics, for inspection without an exporter.
+
+use crate::{
+    events::{MetricData, MetricKind, MetricMetadata, MetricOperation},
+    expiry::SeriesKey,
+    sink::MetricSink,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// The number of recent samples kept for each histogram by default.
+const DEFAULT_HISTOGRAM_SAMPLES: usize = 1024;
+
+/// A sink keeping the current value of every series in memory.
+///
+/// Counters and gauges hold their current value. Histograms keep exact totals and a window of
+/// the most recent samples, so memory use stays bounded. Cloning the store gives another handle
+/// to the same data, so one clone can be passed to [`IPCCollector::sink`](crate::IPCCollector::sink)
+/// and another used to take [snapshots](Self::snapshot).
+///
+/// # Example
+/// ```rust,no_run
+/// use metrics_ipc_collector::{IPCCollector, MemoryStore};
+/// let store = MemoryStore::default();
+/// IPCCollector::default().sink(store.clone()).start_collecting()?;
+///
+/// for series in store.snapshot().series {
+///     println!("{}{:?} = {:?}", series.name, series.labels, series.value);
+/// }
+/// # Ok::<(), metrics_ipc_collector::MetricsError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<Registry>>,
+    histogram_samples: usize,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    series: HashMap<SeriesKey, SeriesValue>,
+    metadata: HashMap<(MetricKind, String), MetricMetadata>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTOGRAM_SAMPLES)
+    }
+}
+
+impl MemoryStore {
+    /// Creates a store keeping up to `histogram_samples` recent samples for each histogram.
+    #[must_use]
+    pub fn new(histogram_samples: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Registry::default())),
+            histogram_samples,
+        }
+    }
+
+    /// Returns a copy of every series and metadata currently held, sorted by name and labels.
+    ///
+    /// # Panics
+    /// Panics if a thread panicked while updating the store.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        let registry = self.inner.lock().unwrap();
+        let mut series: Vec<SeriesSnapshot> = registry
+            .series
+            .iter()
+            .map(|(key, value)| {
+                let metadata = registry.metadata.get(&(key.kind, key.name.clone()));
+                SeriesSnapshot {
+                    name: key.name.clone(),
+                    labels: key.labels.clone(),
+                    value: value.clone(),
+                    description: metadata.map(|m| m.description.clone()),
+                    unit: metadata.and_then(|m| m.unit.clone()),
+                }
+            })
+            .collect();
+        let mut metadata: Vec<MetricMetadata> = registry.metadata.values().cloned().collect();
+        drop(registry);
+
+        series.sort_by(|a, b| (&a.name, &a.labels).cmp(&(&b.name, &b.labels)));
+        metadata.sort_by(|a, b| a.name.cmp(&b.name));
+        Snapshot { series, metadata }
+    }
+}
+
+impl MetricSink for MemoryStore {
+    fn handle_metric(&self, metric: MetricData) {
+        let key = SeriesKey::from(&metric);
+        self.inner
+            .lock()
+            .unwrap()
+            .series
+            .entry(key)
+            .or_insert_with(|| SeriesValue::empty(metric.operation.kind()))
+            .apply(metric.operation, self.histogram_samples);
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        self.inner
+            .lock()
+            .unwrap()
+            .metadata
+            .insert((metadata.kind, metadata.name.clone()), metadata);
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, labels: &BTreeMap<String, String>) {
+        self.inner.lock().unwrap().series.remove(&SeriesKey {
+            kind,
+            name: name.to_string(),
+            labels: labels.clone(),
+        });
+    }
+}
+
+/// Every series and metadata held by a [`MemoryStore`] at one point in time.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    /// Every series, sorted by name and labels.
+    pub series: Vec<SeriesSnapshot>,
+    /// Every metadata event received, sorted by name.
+    pub metadata: Vec<MetricMetadata>,
+}
+
+impl Snapshot {
+    /// Returns the series with exactly the given name and labels, if any.
+    ///
+    /// # Example
+    /// ```rust
+    /// use metrics_ipc_collector::{MemoryStore, MetricData, MetricOperation, MetricSink, SeriesValue};
+    /// let store = MemoryStore::default();
+    /// store.handle_metric(MetricData {
+    ///     name: "jobs".to_string(),
+    ///     labels: [("queue".to_string(), "high".to_string())].into(),
+    ///     operation: MetricOperation::IncrementCounter(3),
+    /// });
+    ///
+    /// let snapshot = store.snapshot();
+    /// let jobs = snapshot.get("jobs", &[("queue", "high")]).unwrap();
+    /// assert!(matches!(jobs.value, SeriesValue::Counter(3)));
+    /// ```
+    #[must_use]
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<&SeriesSnapshot> {
+        let labels: BTreeMap<String, String> = labels
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        self.series
+            .iter()
+            .find(|series| series.name == name && series.labels == labels)
+    }
+}
+
+/// A single series held by a [`MemoryStore`].
+#[derive(Debug, Clone)]
+pub struct SeriesSnapshot {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: SeriesValue,
+    /// The description of the metric, if its metadata has been received.
+    pub description: Option<String>,
+    /// The unit of the metric, if its metadata has been received and has one.
+    pub unit: Option<String>,
+}
+
+/// The current value of a series held by a [`MemoryStore`].
+#[derive(Debug, Clone)]
+pub enum SeriesValue {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Distribution),
+}
+
+impl SeriesValue {
+    const fn empty(kind: MetricKind) -> Self {
+        match kind {
+            MetricKind::Counter => Self::Counter(0),
+            MetricKind::Gauge => Self::Gauge(0.0),
+            MetricKind::Histogram => Self::Histogram(Distribution::new()),
+        }
+    }
+
+    fn apply(&mut self, operation: MetricOperation, histogram_samples: usize) {
+        match (self, operation) {
+            (Self::Counter(total), MetricOperation::IncrementCounter(value)) => {
+                *total = total.saturating_add(value);
+            }
+            (Self::Counter(total), MetricOperation::SetCounter(value)) => {
+                *total = (*total).max(value);
+            }
+            (Self::Gauge(gauge), MetricOperation::IncrementGauge(value)) => *gauge += value,
+            (Self::Gauge(gauge), MetricOperation::DecrementGauge(value)) => *gauge -= value,
+            (Self::Gauge(gauge), MetricOperation::SetGauge(value)) => *gauge = value,
+            (Self::Histogram(distribution), MetricOperation::RecordHistogram(value)) => {
+                distribution.record(value, histogram_samples);
+            }
+            // The key includes the kind, so operations always match the stored value.
+            _ => {}
+        }
+    }
+}
+
+/// The values recorded to a histogram.
+///
+/// The count, sum, minimum and maximum cover every value recorded, while `samples` only holds the
+/// most recent ones, oldest first.
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub samples: VecDeque<f64>,
+}
+
+impl Distribution {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, value: f64, max_samples: usize) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if max_samples == 0 {
+            return;
+        }
+        if self.samples.len() == max_samples {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    /// Returns the mean of every value recorded, or `None` if nothing has been recorded.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// Returns the `q` quantile of the retained samples, between 0 and 1, using the nearest rank.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let rank = (q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64).round() as usize;
+        Some(sorted[rank])
+    }
+}
@@ -0,0 +1,209 @@
+//! Metrics the collector reports about its own health.
+//!
+//! All series are prefixed with `metrics_ipc_collector_` and are dispatched to the same
+//! [`MetricSink`] as the collected metrics, so they are exported alongside them.
+
+use crate::{
+    events::{MetricData, MetricKind, MetricMetadata, MetricOperation},
+    sink::MetricSink,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+const CONNECTIONS_ACTIVE: &str = "metrics_ipc_collector_connections_active";
+const CONNECTIONS_ACCEPTED: &str = "metrics_ipc_collector_connections_accepted_total";
+const CONNECTIONS_CLOSED: &str = "metrics_ipc_collector_connections_closed_total";
+const CONNECTIONS_REJECTED: &str = "metrics_ipc_collector_connections_rejected_total";
+const FRAMES_DECODED: &str = "metrics_ipc_collector_frames_decoded_total";
+const DECODE_ERRORS: &str = "metrics_ipc_collector_decode_errors_total";
+const BYTES_RECEIVED: &str = "metrics_ipc_collector_bytes_received_total";
+const EVENTS_FILTERED: &str = "metrics_ipc_collector_events_filtered_total";
+const SERIES_REJECTED: &str = "metrics_ipc_collector_series_rejected_total";
+const FRAMES_CORRUPT: &str = "metrics_ipc_collector_frames_corrupt_total";
+const FRAMES_OVERSIZE: &str = "metrics_ipc_collector_frames_oversize_total";
+const CONNECTIONS_DROPPED: &str = "metrics_ipc_collector_connections_dropped_total";
+const CONNECTIONS_TIMED_OUT: &str = "metrics_ipc_collector_connections_timed_out_total";
+const METADATA_CONFLICTS: &str = "metrics_ipc_collector_metadata_conflicts_total";
+
+const DESCRIPTIONS: &[(&str, MetricKind, Option<&str>, &str)] = &[
+    (
+        CONNECTIONS_ACTIVE,
+        MetricKind::Gauge,
+        None,
+        "Number of client connections currently open.",
+    ),
+    (
+        CONNECTIONS_ACCEPTED,
+        MetricKind::Counter,
+        None,
+        "Total number of client connections accepted.",
+    ),
+    (
+        CONNECTIONS_CLOSED,
+        MetricKind::Counter,
+        None,
+        "Total number of client connections closed.",
+    ),
+    (
+        CONNECTIONS_REJECTED,
+        MetricKind::Counter,
+        None,
+        "Total number of client connections rejected by the peer allowlist.",
+    ),
+    (
+        FRAMES_DECODED,
+        MetricKind::Counter,
+        None,
+        "Total number of frames decoded into metric events.",
+    ),
+    (
+        DECODE_ERRORS,
+        MetricKind::Counter,
+        None,
+        "Total number of frames that could not be decoded.",
+    ),
+    (
+        BYTES_RECEIVED,
+        MetricKind::Counter,
+        Some("bytes"),
+        "Total number of bytes received from clients.",
+    ),
+    (
+        EVENTS_FILTERED,
+        MetricKind::Counter,
+        None,
+        "Total number of events dropped by the metric name filters.",
+    ),
+    (
+        SERIES_REJECTED,
+        MetricKind::Counter,
+        None,
+        "Total number of metric updates dropped or collapsed by the series limits.",
+    ),
+    (
+        FRAMES_CORRUPT,
+        MetricKind::Counter,
+        None,
+        "Total number of corrupt frames skipped while resynchronizing.",
+    ),
+    (
+        FRAMES_OVERSIZE,
+        MetricKind::Counter,
+        None,
+        "Total number of frames discarded for exceeding the maximum frame size.",
+    ),
+    (
+        CONNECTIONS_DROPPED,
+        MetricKind::Counter,
+        None,
+        "Total number of client connections closed for sending oversize or too many bad frames.",
+    ),
+    (
+        CONNECTIONS_TIMED_OUT,
+        MetricKind::Counter,
+        None,
+        "Total number of client connections closed for staying idle past the idle timeout.",
+    ),
+    (
+        METADATA_CONFLICTS,
+        MetricKind::Counter,
+        None,
+        "Total number of events dropped for conflicting with how their metric was first described.",
+    ),
+];
+
+/// Reports the collector's own metrics to its sink, when enabled.
+#[derive(Clone)]
+pub struct Telemetry {
+    sink: Option<Arc<dyn MetricSink>>,
+}
+
+impl Telemetry {
+    /// Creates telemetry reporting to `sink`, or discarding everything if `enabled` is `false`.
+    pub fn new(sink: &Arc<dyn MetricSink>, enabled: bool) -> Self {
+        Self {
+            sink: enabled.then(|| Arc::clone(sink)),
+        }
+    }
+
+    /// Describes every telemetry series to the sink.
+    pub fn describe(&self) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        for (name, kind, unit, description) in DESCRIPTIONS {
+            sink.handle_metadata(MetricMetadata {
+                name: (*name).to_string(),
+                kind: *kind,
+                description: (*description).to_string(),
+                unit: unit.map(ToString::to_string),
+            });
+        }
+    }
+
+    pub fn connection_accepted(&self) {
+        self.emit(CONNECTIONS_ACCEPTED, MetricOperation::IncrementCounter(1));
+        self.emit(CONNECTIONS_ACTIVE, MetricOperation::IncrementGauge(1.0));
+    }
+
+    pub fn connection_closed(&self) {
+        self.emit(CONNECTIONS_CLOSED, MetricOperation::IncrementCounter(1));
+        self.emit(CONNECTIONS_ACTIVE, MetricOperation::DecrementGauge(1.0));
+    }
+
+    pub fn connection_rejected(&self) {
+        self.emit(CONNECTIONS_REJECTED, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn bytes_received(&self, bytes: usize) {
+        self.emit(
+            BYTES_RECEIVED,
+            MetricOperation::IncrementCounter(bytes as u64),
+        );
+    }
+
+    pub fn frame_decoded(&self) {
+        self.emit(FRAMES_DECODED, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn decode_error(&self) {
+        self.emit(DECODE_ERRORS, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn event_filtered(&self) {
+        self.emit(EVENTS_FILTERED, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn series_rejected(&self) {
+        self.emit(SERIES_REJECTED, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn frame_corrupt(&self) {
+        self.emit(FRAMES_CORRUPT, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn frame_oversize(&self) {
+        self.emit(FRAMES_OVERSIZE, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn connection_dropped(&self) {
+        self.emit(CONNECTIONS_DROPPED, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn connection_timed_out(&self) {
+        self.emit(CONNECTIONS_TIMED_OUT, MetricOperation::IncrementCounter(1));
+    }
+
+    pub fn metadata_conflict(&self) {
+        self.emit(METADATA_CONFLICTS, MetricOperation::IncrementCounter(1));
+    }
+
+    fn emit(&self, name: &str, operation: MetricOperation) {
+        if let Some(sink) = &self.sink {
+            sink.handle_metric(MetricData {
+                name: name.to_string(),
+                labels: BTreeMap::new(),
+                operation,
+            });
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Checks that the collector takes over sockets bound before it starts, as systemd passes them.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, socket_dir, start, try_start};
+use metrics_ipc_collector::{IPCCollector, IPCRecorder, MetricsError};
+use std::{
+    io::ErrorKind,
+    net::TcpListener,
+    os::{
+        fd::OwnedFd,
+        unix::net::{UnixDatagram, UnixListener, UnixStream},
+    },
+};
+
+#[test]
+fn accepts_clients_on_a_prebound_unix_socket() {
+    let dir = socket_dir("activation-unix");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+    let fd = OwnedFd::from(UnixListener::bind(&socket).unwrap());
+
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::from(fd)
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("activated_total").increment(2);
+    });
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "activated_total");
+    // The socket belongs to whoever bound it, so the collector leaves the file alone.
+    assert!(socket.exists());
+}
+
+#[test]
+fn accepts_clients_on_a_prebound_tcp_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let sink = CaptureSink::default();
+    let running = start(
+        IPCCollector::from(OwnedFd::from(listener))
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+    assert_eq!(running.handle.tcp_addr(), Some(addr));
+
+    let recorder = IPCRecorder::from_tcp(std::net::TcpStream::connect(addr).unwrap());
+    metrics::with_local_recorder(&recorder, || {
+        metrics::gauge!("activated").set(1.0);
+    });
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "activated");
+}
+
+#[test]
+fn receives_datagrams_on_a_prebound_datagram_socket() {
+    let dir = socket_dir("activation-datagram");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.dgram");
+    let fd = OwnedFd::from(UnixDatagram::bind(&socket).unwrap());
+
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::from(fd)
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    let sender = UnixDatagram::unbound().unwrap();
+    sender.connect(&socket).unwrap();
+    let recorder = IPCRecorder::from_datagram(sender);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("activated_total").increment(1);
+    });
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "activated_total");
+}
+
+#[test]
+fn rejects_sockets_that_are_not_listening() {
+    // Connected sockets would fail every accept.
+    let (stream, _peer) = UnixStream::pair().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    for fd in [OwnedFd::from(stream), OwnedFd::from(tcp)] {
+        assert!(matches!(
+            try_start(IPCCollector::from(fd).self_telemetry(false)),
+            Err(MetricsError::Io(e)) if e.kind() == ErrorKind::InvalidInput
+        ));
+    }
+}
+
+#[test]
+fn fails_without_activated_sockets() {
+    assert!(matches!(
+        IPCCollector::from_listen_fds(),
+        Err(MetricsError::NotActivated)
+    ));
+}
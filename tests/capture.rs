@@ -0,0 +1,133 @@
+//! Checks that captured frames can be replayed through another collector.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, Running, connect_recorder, socket_dir, start};
+use metrics_ipc_collector::{IPCCollector, MetricsError, PeerLabel};
+use std::path::Path;
+
+fn replay_at(running: &Running, path: &Path, speed: f64) -> Result<(), MetricsError> {
+    #[cfg(feature = "tokio")]
+    {
+        running.block_on(running.handle.replay(path, speed))
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        running.handle.replay(path, speed)
+    }
+}
+
+fn replay(running: &Running, path: &Path) -> Result<(), MetricsError> {
+    replay_at(running, path, f64::INFINITY)
+}
+
+#[test]
+fn replays_captured_frames() {
+    let dir = socket_dir("capture");
+    let socket = dir.join("collector.sock");
+    let capture = dir.join("frames.cap");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .capture(&capture)
+            .peer_labels(&[PeerLabel::Pid])
+            .label("tier", "system")
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_gauge!("queue_depth", "Jobs waiting.");
+        metrics::gauge!("queue_depth").set(3.0);
+        metrics::counter!("jobs", "queue" => "high").increment(2);
+    });
+    let received = sink.wait_for_metrics(2);
+
+    let replayed = CaptureSink::default();
+    let running = start(
+        IPCCollector::default()
+            .socket_file(dir.join("replay.sock"))
+            .self_telemetry(false)
+            .sink(replayed.clone()),
+    );
+    replay(&running, &capture).unwrap();
+
+    // Including the peer and listener labels added by the original collector.
+    let metrics = replayed.wait_for_metrics(2);
+    assert_eq!(metrics[0].labels["tier"], "system");
+    assert_eq!(format!("{metrics:?}"), format!("{received:?}"));
+    assert_eq!(replayed.wait_for_metadata(1)[0].name, "queue_depth");
+}
+
+#[test]
+fn rotates_the_capture_file() {
+    let dir = socket_dir("capture_rotation");
+    let socket = dir.join("collector.sock");
+    let capture = dir.join("frames.cap");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .capture(&capture)
+            .capture_rotation(64, 1)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        for i in 0..3 {
+            metrics::counter!("jobs", "attempt" => i.to_string()).increment(1);
+        }
+    });
+    sink.wait_for_metrics(3);
+
+    let mut rotated = capture.clone().into_os_string();
+    rotated.push(".1");
+    assert!(Path::new(&rotated).exists());
+    assert!(!dir.join("frames.cap.2").exists());
+
+    // The oldest frame has been rotated away, the rest replay in order.
+    let replayed = CaptureSink::default();
+    let running = start(
+        IPCCollector::default()
+            .socket_file(dir.join("replay.sock"))
+            .self_telemetry(false)
+            .sink(replayed.clone()),
+    );
+    replay(&running, Path::new(&rotated)).unwrap();
+    replay(&running, &capture).unwrap();
+    let attempts: Vec<_> = replayed
+        .wait_for_metrics(2)
+        .iter()
+        .map(|m| m.labels["attempt"].clone())
+        .collect();
+    assert_eq!(attempts, ["1", "2"]);
+
+    assert!(replay(&running, &dir.join("collector.sock")).is_err());
+}
+
+#[test]
+fn rejects_invalid_replay_speeds() {
+    let dir = socket_dir("capture_speed");
+    let capture = dir.join("frames.cap");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&capture, b"MIPCCAP1").unwrap();
+    let running = start(
+        IPCCollector::default()
+            .socket_file(dir.join("replay.sock"))
+            .self_telemetry(false),
+    );
+
+    for speed in [0.0, -1.0, f64::NAN] {
+        assert!(matches!(
+            replay_at(&running, &capture, speed),
+            Err(MetricsError::ReplaySpeed(_))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+//! Checks that series limits drop or collapse series beyond the configured counts.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::CardinalityOverflow;
+
+#[test]
+fn drops_series_over_the_per_metric_limit() {
+    let (collector, socket) = test_collector("cardinality_drop");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .max_series_per_metric(2)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        for id in ["a", "b", "c", "a"] {
+            metrics::counter!("requests", "request_id" => id).increment(1);
+        }
+        metrics::counter!("errors", "request_id" => "c").increment(1);
+    });
+
+    let metrics = sink.wait_for_metrics(4);
+    let series: Vec<_> = metrics
+        .iter()
+        .map(|m| (m.name.as_str(), m.labels["request_id"].as_str()))
+        .collect();
+    assert_eq!(
+        series,
+        [
+            ("requests", "a"),
+            ("requests", "b"),
+            ("requests", "a"),
+            ("errors", "c"),
+        ]
+    );
+}
+
+#[test]
+fn collapses_series_over_the_global_limit() {
+    let (collector, socket) = test_collector("cardinality_collapse");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .max_series(1)
+            .cardinality_overflow(CardinalityOverflow::Collapse)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        for id in ["a", "b", "c"] {
+            metrics::counter!("requests", "request_id" => id).increment(1);
+        }
+    });
+
+    // accepted + active, then bytes + decoded, a rejection for all but the first, and the counter.
+    let metrics = sink.wait_for_metrics(13);
+    let requests: Vec<_> = metrics
+        .iter()
+        .filter(|m| m.name == "requests")
+        .map(|m| m.labels["request_id"].as_str())
+        .collect();
+    assert_eq!(requests, ["a", "other", "other"]);
+    let rejected = metrics
+        .iter()
+        .filter(|m| m.name == "metrics_ipc_collector_series_rejected_total")
+        .count();
+    assert_eq!(rejected, 2);
+}
@@ -0,0 +1,77 @@
+//! Checks that per-source state follows a client id across reconnects.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::{GaugeMerge, MetricOperation};
+
+#[test]
+fn resumes_counter_totals_after_reconnect() {
+    let (collector, socket) = test_collector("client-id");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .gauge_merge("in_flight", GaugeMerge::PerSource)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    recorder.identify("worker-1").unwrap();
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("jobs").absolute(10);
+        metrics::gauge!("in_flight").set(1.0);
+    });
+    sink.wait_for_metrics(2);
+    drop(recorder);
+
+    let recorder = connect_recorder(&socket);
+    recorder.identify("worker-1").unwrap();
+    metrics::with_local_recorder(&recorder, || metrics::counter!("jobs").absolute(12));
+
+    let metrics = sink.wait_for_metrics(3);
+    assert_eq!(metrics[1].labels["source"], "worker-1");
+    // Without the client id the reconnected process would be a new source counting 12 jobs.
+    assert!(matches!(
+        metrics[2].operation,
+        MetricOperation::IncrementCounter(2)
+    ));
+}
+
+#[test]
+fn keeps_a_second_connection_with_the_same_id_separate() {
+    let (collector, socket) = test_collector("client-id-duplicate");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .gauge_merge("in_flight", GaugeMerge::PerSource)
+            .sink(sink.clone()),
+    );
+
+    let first = connect_recorder(&socket);
+    first.identify("worker-1").unwrap();
+    metrics::with_local_recorder(&first, || {
+        metrics::counter!("jobs").absolute(10);
+        metrics::gauge!("in_flight").set(1.0);
+    });
+    sink.wait_for_metrics(2);
+
+    let second = connect_recorder(&socket);
+    second.identify("worker-1").unwrap();
+    metrics::with_local_recorder(&second, || {
+        metrics::counter!("jobs").absolute(14);
+        metrics::gauge!("in_flight").set(2.0);
+    });
+
+    let metrics = sink.wait_for_metrics(4);
+    // Sharing the first connection's total would have counted only 4 of the second process's
+    // jobs, and merged both gauges into one series.
+    assert!(matches!(
+        metrics[2].operation,
+        MetricOperation::IncrementCounter(14)
+    ));
+    assert_eq!(metrics[1].labels["source"], "worker-1");
+    assert_ne!(metrics[3].labels["source"], "worker-1");
+}
@@ -0,0 +1,143 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use interprocess::local_socket::{GenericFilePath, prelude::*};
+use metrics_ipc_collector::{
+    CollectorHandle, IPCCollector, IPCRecorder, MetricData, MetricKind, MetricMetadata, MetricSink,
+    MetricsError, ReceivedEvent, Subscription,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Keeps whatever the collector needs to keep running alive for the duration of a test.
+pub struct Running {
+    pub handle: CollectorHandle,
+    #[cfg(feature = "tokio")]
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Running {
+    /// Runs a future to completion on the collector's runtime.
+    #[cfg(feature = "tokio")]
+    pub fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+/// Starts the collector, providing a Tokio runtime for it when the `tokio` feature is enabled.
+pub fn start(collector: IPCCollector) -> Running {
+    try_start(collector).unwrap()
+}
+
+/// Starts the collector as [`start`] does, returning the error if it fails to start.
+pub fn try_start(collector: IPCCollector) -> Result<Running, MetricsError> {
+    #[cfg(feature = "tokio")]
+    {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let handle = runtime.block_on(async { collector.start_collecting() })?;
+        Ok(Running { handle, runtime })
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        let handle = collector.start_collecting()?;
+        Ok(Running { handle })
+    }
+}
+
+/// Waits up to five seconds for the next event of a subscription.
+pub fn next_event(running: &Running, subscription: &mut Subscription) -> Option<ReceivedEvent> {
+    #[cfg(feature = "tokio")]
+    {
+        running.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), subscription.recv())
+                .await
+                .ok()
+                .flatten()
+        })
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        let _ = running;
+        subscription.recv_timeout(Duration::from_secs(5))
+    }
+}
+
+/// Returns a fresh, not yet created, directory for the sockets of a single test.
+pub fn socket_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "metrics_ipc_collector-{}-{test}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Returns a collector listening on a socket file in a fresh directory for a single test, along
+/// with the path of the socket.
+pub fn test_collector(test: &str) -> (IPCCollector, PathBuf) {
+    let socket = socket_dir(test).join("collector.sock");
+    let collector = IPCCollector::default()
+        .socket_file(&socket)
+        .socket_dir_mode(0o700);
+    (collector, socket)
+}
+
+/// A sink recording everything the collector dispatches to it.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureSink {
+    metrics: Arc<Mutex<Vec<MetricData>>>,
+    metadata: Arc<Mutex<Vec<MetricMetadata>>>,
+    removed: Arc<Mutex<Vec<(MetricKind, String)>>>,
+}
+
+impl CaptureSink {
+    /// Waits until at least `count` metrics have been captured, returning them.
+    pub fn wait_for_metrics(&self, count: usize) -> Vec<MetricData> {
+        wait_for(&self.metrics, count)
+    }
+
+    /// Waits until at least `count` metadata events have been captured, returning them.
+    pub fn wait_for_metadata(&self, count: usize) -> Vec<MetricMetadata> {
+        wait_for(&self.metadata, count)
+    }
+
+    /// Waits until at least `count` series have been removed, returning their kinds and names.
+    pub fn wait_for_removed(&self, count: usize) -> Vec<(MetricKind, String)> {
+        wait_for(&self.removed, count)
+    }
+}
+
+impl MetricSink for CaptureSink {
+    fn handle_metric(&self, metric: MetricData) {
+        self.metrics.lock().unwrap().push(metric);
+    }
+
+    fn handle_metadata(&self, metadata: MetricMetadata) {
+        self.metadata.lock().unwrap().push(metadata);
+    }
+
+    fn remove_metric(&self, kind: MetricKind, name: &str, _labels: &BTreeMap<String, String>) {
+        self.removed.lock().unwrap().push((kind, name.to_string()));
+    }
+}
+
+fn wait_for<T: Clone>(items: &Mutex<Vec<T>>, count: usize) -> Vec<T> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let items = items.lock().unwrap().clone();
+        if items.len() >= count || Instant::now() > deadline {
+            return items;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Connects an `IPCRecorder` to the socket file at `socket`.
+pub fn connect_recorder(socket: &Path) -> IPCRecorder {
+    let name = socket.to_fs_name::<GenericFilePath>().unwrap();
+    IPCRecorder::new(LocalSocketStream::connect(name).unwrap())
+}
@@ -0,0 +1,78 @@
+//! Checks that events conflicting with how a metric was first described are dropped and reported.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::{MetricEvent, MetricKind};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn drops_and_reports_conflicting_events() {
+    let (collector, socket) = test_collector("conflict");
+    let sink = CaptureSink::default();
+    let conflicts = Arc::new(Mutex::new(Vec::new()));
+    let reported = Arc::clone(&conflicts);
+    let _running = start(
+        collector
+            .on_metadata_conflict(move |conflict| {
+                reported.lock().unwrap().push(conflict.clone());
+            })
+            .sink(sink.clone()),
+    );
+
+    let first = connect_recorder(&socket);
+    metrics::with_local_recorder(&first, || {
+        metrics::describe_counter!("requests", metrics::Unit::Count, "Requests served.");
+        metrics::counter!("requests").increment(1);
+    });
+    assert!(
+        sink.wait_for_metadata(15)
+            .iter()
+            .any(|m| m.name == "requests")
+    );
+
+    let second = connect_recorder(&socket);
+    metrics::with_local_recorder(&second, || {
+        // Compatible: same kind, and no unit never conflicts.
+        metrics::describe_counter!("requests", "Requests served.");
+        metrics::describe_gauge!("requests", "Requests in flight.");
+        metrics::gauge!("requests").set(4.0);
+        metrics::counter!("requests").increment(2);
+    });
+
+    let metrics = sink.wait_for_metrics(20);
+    let requests: Vec<_> = metrics
+        .iter()
+        .filter(|m| m.name == "requests")
+        .map(|m| m.operation.kind())
+        .collect();
+    assert_eq!(requests, [MetricKind::Counter, MetricKind::Counter]);
+    let counted = metrics
+        .iter()
+        .filter(|m| m.name == "metrics_ipc_collector_metadata_conflicts_total")
+        .count();
+    assert_eq!(counted, 2);
+
+    // The description without a unit is forwarded with the unit it was first described with.
+    let metadata = sink.wait_for_metadata(16);
+    let requests: Vec<_> = metadata.iter().filter(|m| m.name == "requests").collect();
+    assert_eq!(requests.len(), 2);
+    assert!(
+        requests
+            .iter()
+            .all(|m| m.kind == MetricKind::Counter && m.unit.as_deref() == Some("count"))
+    );
+
+    let conflicts = conflicts.lock().unwrap();
+    assert_eq!(conflicts.len(), 2);
+    assert!(
+        conflicts
+            .iter()
+            .all(|c| c.declared.kind == MetricKind::Counter && c.connection_id == 1)
+    );
+    assert!(
+        matches!(&conflicts[0].conflicting, MetricEvent::Metadata(m) if m.kind == MetricKind::Gauge)
+    );
+    assert!(matches!(&conflicts[1].conflicting, MetricEvent::Metric(m) if m.name == "requests"));
+}
@@ -0,0 +1,34 @@
+//! Checks that absolute counter values from several clients are summed.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::MetricOperation;
+
+#[test]
+fn sums_absolute_counters_across_clients() {
+    let (collector, socket) = test_collector("counters");
+    let sink = CaptureSink::default();
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let first = connect_recorder(&socket);
+    let second = connect_recorder(&socket);
+    let mut expected = 0;
+    for (recorder, total) in [(&first, 10), (&second, 5), (&first, 12), (&first, 3)] {
+        metrics::with_local_recorder(recorder, || metrics::counter!("jobs").absolute(total));
+        expected += 1;
+        sink.wait_for_metrics(expected);
+    }
+
+    let increments: Vec<_> = sink
+        .wait_for_metrics(4)
+        .iter()
+        .map(|metric| match metric.operation {
+            MetricOperation::IncrementCounter(value) => value,
+            ref operation => panic!("expected an increment, got {operation:?}"),
+        })
+        .collect();
+    // The first client going from 12 back to 3 is a restart, counting 3 new jobs.
+    assert_eq!(increments, [10, 5, 2, 3]);
+}
@@ -0,0 +1,79 @@
+//! Checks that the collector receives one event per datagram on a Unix datagram socket, and that
+//! recorders keep sending datagrams across collector restarts.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, socket_dir, start};
+use metrics_ipc_collector::{
+    IPCCollector, IPCRecorder, IPCRecorderBuilder, MetricEvent, MetricOperation,
+};
+use std::{os::unix::net::UnixDatagram, time::Duration};
+
+#[test]
+fn receives_metrics_as_datagrams() {
+    let dir = socket_dir("datagram");
+    let socket = dir.join("collector.dgram");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .datagram(&socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    // Each sender is short-lived, as a hook or signal handler would be.
+    for value in [1, 2] {
+        let sender = UnixDatagram::unbound().unwrap();
+        sender.connect(&socket).unwrap();
+        let recorder = IPCRecorder::from_datagram(sender);
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("hooks_run_total", "hook" => "pre-stop").increment(value);
+        });
+    }
+
+    let metrics = sink.wait_for_metrics(2);
+    let increments: Vec<_> = metrics
+        .iter()
+        .map(|m| match m.operation {
+            MetricOperation::IncrementCounter(value) => value,
+            _ => panic!("unexpected operation {:?}", m.operation),
+        })
+        .collect();
+    assert_eq!(increments, [1, 2]);
+    assert!(metrics.iter().all(|m| m.labels["hook"] == "pre-stop"));
+    assert!(socket.exists());
+}
+
+#[test]
+fn keeps_sending_after_the_collector_restarts() {
+    let dir = socket_dir("datagram-restart");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.dgram");
+    let bind = || {
+        let _ = std::fs::remove_file(&socket);
+        let collector = UnixDatagram::bind(&socket).unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        collector
+    };
+
+    // The only test here recording through the global recorder.
+    let first = bind();
+    IPCRecorderBuilder::default()
+        .datagram(&socket)
+        .build()
+        .unwrap();
+    metrics::counter!("before_restart_total").increment(1);
+    assert!(first.recv(&mut [0; 1024]).unwrap() > 0);
+    drop(first);
+
+    let second = bind();
+    metrics::counter!("after_restart_total").increment(1);
+    let mut buffer = [0; 1024];
+    let length = second.recv(&mut buffer).unwrap();
+    let event = MetricEvent::try_from(&buffer[..length].to_vec()).unwrap();
+    assert!(matches!(event, MetricEvent::Metric(m) if m.name == "after_restart_total"));
+}
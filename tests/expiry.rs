@@ -0,0 +1,81 @@
+//! Checks that series from disconnected clients are expired according to the disconnect policy.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::{DisconnectPolicy, IPCCollector, MetricKind, MetricOperation};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+fn collector(test: &str, policy: DisconnectPolicy, sink: &CaptureSink) -> (IPCCollector, PathBuf) {
+    let (collector, socket) = test_collector(test);
+    let collector = collector
+        .self_telemetry(false)
+        .disconnect_policy(policy)
+        .sink(sink.clone());
+    (collector, socket)
+}
+
+#[test]
+fn removes_series_when_last_client_disconnects() {
+    let sink = CaptureSink::default();
+    let (collector, socket) = collector("expiry-remove", DisconnectPolicy::Remove, &sink);
+    let _running = start(collector);
+
+    let first = connect_recorder(&socket);
+    let second = connect_recorder(&socket);
+    metrics::with_local_recorder(&first, || metrics::gauge!("in_flight").set(2.0));
+    metrics::with_local_recorder(&second, || metrics::gauge!("in_flight").set(1.0));
+    sink.wait_for_metrics(2);
+
+    drop(first);
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(sink.wait_for_removed(0).is_empty());
+
+    drop(second);
+    assert_eq!(
+        sink.wait_for_removed(1),
+        [(MetricKind::Gauge, "in_flight".to_string())]
+    );
+}
+
+#[test]
+fn zeroes_gauges_when_client_disconnects() {
+    let sink = CaptureSink::default();
+    let (collector, socket) = collector("expiry-zero", DisconnectPolicy::Zero, &sink);
+    let _running = start(collector);
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::gauge!("in_flight").set(3.0);
+        metrics::counter!("requests").increment(1);
+    });
+    sink.wait_for_metrics(2);
+    drop(recorder);
+
+    let metrics = sink.wait_for_metrics(3);
+    assert_eq!(metrics.len(), 3);
+    assert_eq!(metrics[2].name, "in_flight");
+    assert!(matches!(metrics[2].operation, MetricOperation::SetGauge(v) if v == 0.0));
+}
+
+#[test]
+fn removes_series_after_grace_period() {
+    let sink = CaptureSink::default();
+    let grace = Duration::from_millis(300);
+    let (collector, socket) =
+        collector("expiry-grace", DisconnectPolicy::RemoveAfter(grace), &sink);
+    let _running = start(collector);
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || metrics::gauge!("in_flight").set(1.0));
+    sink.wait_for_metrics(1);
+    let disconnected = Instant::now();
+    drop(recorder);
+
+    assert_eq!(sink.wait_for_removed(1).len(), 1);
+    assert!(disconnected.elapsed() >= grace);
+}
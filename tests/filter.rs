@@ -0,0 +1,49 @@
+//! Checks that allow and deny lists drop events by metric name.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::NamePattern;
+
+#[test]
+fn drops_events_outside_the_allow_list_or_in_the_deny_list() {
+    let (collector, socket) = test_collector("filter");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .allow_metrics("app_*")
+            .allow_metrics(NamePattern::regex("(jobs|queue)_total").unwrap())
+            .deny_metrics("app_debug_*")
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_counter!("tokio_polls", "Noise from a dependency.");
+        metrics::counter!("tokio_polls").increment(1);
+        metrics::counter!("app_debug_loops").increment(1);
+        metrics::counter!("jobs_total_extra").increment(1);
+        metrics::counter!("jobs_total").increment(1);
+        metrics::counter!("app_requests").increment(1);
+    });
+
+    // accepted + active, then bytes + decoded and either a drop or the event for each frame.
+    let metrics = sink.wait_for_metrics(20);
+    let filtered = metrics
+        .iter()
+        .filter(|m| m.name == "metrics_ipc_collector_events_filtered_total")
+        .count();
+    assert_eq!(filtered, 4);
+    let passed: Vec<_> = metrics
+        .iter()
+        .filter(|m| !m.name.starts_with("metrics_ipc_collector_"))
+        .map(|m| m.name.as_str())
+        .collect();
+    assert_eq!(passed, ["jobs_total", "app_requests"]);
+    assert!(
+        sink.wait_for_metadata(0)
+            .iter()
+            .all(|m| m.name.starts_with("metrics_ipc_collector_"))
+    );
+}
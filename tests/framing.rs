@@ -0,0 +1,166 @@
+//! Checks that the collector resynchronizes after corrupt frames and drops connections that only
+//! send garbage or send oversize frames.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, start, test_collector};
+use interprocess::local_socket::{GenericFilePath, Stream, prelude::*};
+use metrics_ipc_collector::{MetricData, MetricEvent, MetricOperation};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+fn connect(socket: &Path) -> Stream {
+    let stream = Stream::connect(socket.to_fs_name::<GenericFilePath>().unwrap()).unwrap();
+    stream
+        .set_recv_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn payload(name: &str) -> Vec<u8> {
+    Vec::try_from(MetricEvent::Metric(MetricData {
+        name: name.to_string(),
+        labels: BTreeMap::new(),
+        operation: MetricOperation::IncrementCounter(1),
+    }))
+    .unwrap()
+}
+
+fn frame(payload: &[u8], checksum: u32) -> Vec<u8> {
+    let mut frame = vec![0xC1, b'M'];
+    frame.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+    frame.extend_from_slice(&checksum.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn resynchronizes_after_corrupt_frames() {
+    let (collector, socket) = test_collector("framing-resync");
+    let sink = CaptureSink::default();
+    let _running = start(collector.sink(sink.clone()));
+
+    // A newline terminated payload as older recorders send, a frame whose checksum does not
+    // match its payload, then a valid frame, all in one write so they arrive in a single read.
+    let mut data = payload("legacy_total");
+    data.push(b'\n');
+    let corrupt = payload("corrupt_total");
+    data.extend(frame(&corrupt, crc32fast::hash(&corrupt) ^ 1));
+    let good = payload("framed_total");
+    data.extend(frame(&good, crc32fast::hash(&good)));
+    connect(&socket).write_all(&data).unwrap();
+
+    let metrics = sink.wait_for_metrics(8);
+    let received: Vec<_> = metrics
+        .iter()
+        .map(|m| m.name.as_str())
+        .filter(|name| !name.starts_with("metrics_ipc_collector_"))
+        .collect();
+    assert_eq!(received, ["legacy_total", "framed_total"]);
+    assert!(
+        metrics
+            .iter()
+            .any(|m| m.name == "metrics_ipc_collector_frames_corrupt_total")
+    );
+}
+
+#[test]
+fn resynchronizes_after_corrupt_headers() {
+    let (collector, socket) = test_collector("framing-headers");
+    let sink = CaptureSink::default();
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let framed = |name| {
+        let payload = payload(name);
+        frame(&payload, crc32fast::hash(&payload))
+    };
+    // A frame whose magic is corrupt before any valid frame, one after, then a frame announcing
+    // a shorter payload than it carries, each followed by a valid frame.
+    let mut data = framed("bad_magic_total");
+    data[0] = b'x';
+    data.extend(framed("first_total"));
+    let mut bad_magic = framed("bad_magic_total");
+    bad_magic[0] = 0x92;
+    data.extend(bad_magic);
+    data.extend(framed("second_total"));
+    let mut bad_length = framed("bad_length_total");
+    bad_length[2] -= 1;
+    data.extend(bad_length);
+    data.extend(framed("third_total"));
+    connect(&socket).write_all(&data).unwrap();
+
+    let metrics = sink.wait_for_metrics(3);
+    let received: Vec<_> = metrics.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(received, ["first_total", "second_total", "third_total"]);
+}
+
+#[test]
+fn drops_connections_after_consecutive_errors() {
+    let (collector, socket) = test_collector("framing-drop");
+    let sink = CaptureSink::default();
+    let _running = start(collector.max_consecutive_errors(2).sink(sink.clone()));
+
+    // Garbage skipped in one go is a single error, the frame that does not decode the second.
+    let mut data = b"\xC1X\xC1X\xC1X".to_vec();
+    data.extend(frame(b"garbage", crc32fast::hash(b"garbage")));
+    let mut stream = connect(&socket);
+    stream.write_all(&data).unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    assert!(
+        sink.wait_for_metrics(7)
+            .iter()
+            .any(|m| m.name == "metrics_ipc_collector_connections_dropped_total")
+    );
+}
+
+#[test]
+fn keeps_connections_without_an_error_limit() {
+    let (collector, socket) = test_collector("framing-no-limit");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .max_consecutive_errors(0)
+            .sink(sink.clone()),
+    );
+
+    let mut stream = connect(&socket);
+    stream.write_all(b"\xC1X\xC1X\xC1X").unwrap();
+    let good = payload("after_errors_total");
+    stream
+        .write_all(&frame(&good, crc32fast::hash(&good)))
+        .unwrap();
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "after_errors_total");
+}
+
+#[test]
+fn closes_connections_sending_oversize_frames() {
+    let (collector, socket) = test_collector("framing-oversize");
+    let sink = CaptureSink::default();
+    let _running = start(collector.max_frame_size(64).sink(sink.clone()));
+
+    // A frame announcing more than the maximum is rejected from its header alone.
+    let mut header = frame(&[], 0);
+    header[2..6].copy_from_slice(&1024_u32.to_le_bytes());
+    let mut stream = connect(&socket);
+    stream.write_all(&header).unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+    // As is a client that never ends a newline terminated frame.
+    let mut stream = connect(&socket);
+    stream.write_all(&[b'x'; 100]).unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+    // Both connections were counted before they were closed.
+    let oversize = sink
+        .wait_for_metrics(1)
+        .iter()
+        .filter(|m| m.name == "metrics_ipc_collector_frames_oversize_total")
+        .count();
+    assert_eq!(oversize, 2);
+}
@@ -0,0 +1,91 @@
+//! Checks that gauges reported by several clients are merged according to the merge rules.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::{DisconnectPolicy, GaugeMerge, MetricOperation};
+
+fn gauge_value(operation: &MetricOperation) -> f64 {
+    match operation {
+        MetricOperation::SetGauge(value) => *value,
+        operation => panic!("expected a gauge to be set, got {operation:?}"),
+    }
+}
+
+#[test]
+fn sums_gauges_across_clients() {
+    let (collector, socket) = test_collector("merge-sum");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .gauge_merge("*_depth", GaugeMerge::Sum)
+            .disconnect_policy(DisconnectPolicy::Remove)
+            .sink(sink.clone()),
+    );
+
+    let first = connect_recorder(&socket);
+    let second = connect_recorder(&socket);
+    metrics::with_local_recorder(&first, || metrics::gauge!("queue_depth").set(2.0));
+    sink.wait_for_metrics(1);
+    metrics::with_local_recorder(&second, || metrics::gauge!("queue_depth").set(3.0));
+    sink.wait_for_metrics(2);
+    metrics::with_local_recorder(&first, || metrics::gauge!("queue_depth").increment(1.0));
+
+    let metrics = sink.wait_for_metrics(3);
+    let values: Vec<_> = metrics.iter().map(|m| gauge_value(&m.operation)).collect();
+    assert_eq!(values, [2.0, 5.0, 6.0]);
+
+    // The first client's value is dropped from the sum once it disconnects.
+    drop(first);
+    let metrics = sink.wait_for_metrics(4);
+    assert_eq!(gauge_value(&metrics[3].operation), 3.0);
+}
+
+#[test]
+fn keeps_a_series_per_source() {
+    let (collector, socket) = test_collector("merge-per-source");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .gauge_merge("in_flight", GaugeMerge::PerSource)
+            .sink(sink.clone()),
+    );
+
+    let first = connect_recorder(&socket);
+    let second = connect_recorder(&socket);
+    metrics::with_local_recorder(&first, || metrics::gauge!("in_flight").set(1.0));
+    sink.wait_for_metrics(1);
+    metrics::with_local_recorder(&second, || metrics::gauge!("in_flight").set(4.0));
+
+    let metrics = sink.wait_for_metrics(2);
+    assert_ne!(metrics[0].labels["source"], metrics[1].labels["source"]);
+    assert_eq!(gauge_value(&metrics[1].operation), 4.0);
+}
+
+#[test]
+fn drops_disconnected_workers_from_the_sum_whatever_the_policy() {
+    let (collector, socket) = test_collector("merge-keep");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .gauge_merge("*_depth", GaugeMerge::Sum)
+            .sink(sink.clone()),
+    );
+
+    let worker = connect_recorder(&socket);
+    let other = connect_recorder(&socket);
+    metrics::with_local_recorder(&worker, || metrics::gauge!("queue_depth").set(2.0));
+    sink.wait_for_metrics(1);
+    metrics::with_local_recorder(&other, || metrics::gauge!("queue_depth").set(3.0));
+    assert_eq!(gauge_value(&sink.wait_for_metrics(2)[1].operation), 5.0);
+
+    // Series are kept under the default policy, but a closed connection can never report again.
+    drop(worker);
+    let metrics = sink.wait_for_metrics(3);
+    assert_eq!(metrics.len(), 3);
+    assert_eq!(gauge_value(&metrics[2].operation), 3.0);
+}
@@ -0,0 +1,60 @@
+//! Checks that idle connections are closed and handled as disconnects, unless kept alive with
+//! heartbeats.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::{DisconnectPolicy, IPCCollector, MetricKind};
+use std::{path::PathBuf, time::Duration};
+
+fn collector(test: &str, sink: &CaptureSink) -> (IPCCollector, PathBuf) {
+    let (collector, socket) = test_collector(test);
+    let collector = collector
+        .idle_timeout(Duration::from_millis(200))
+        .disconnect_policy(DisconnectPolicy::Remove)
+        .sink(sink.clone());
+    (collector, socket)
+}
+
+#[test]
+fn closes_idle_connections() {
+    let sink = CaptureSink::default();
+    let (collector, socket) = collector("idle-close", &sink);
+    let _running = start(collector);
+
+    // The recorder stays connected but never sends anything again, as if it were stopped.
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || metrics::gauge!("in_flight").set(2.0));
+
+    assert_eq!(
+        sink.wait_for_removed(1),
+        [(MetricKind::Gauge, "in_flight".to_string())]
+    );
+    assert!(
+        sink.wait_for_metrics(1)
+            .iter()
+            .any(|m| m.name == "metrics_ipc_collector_connections_timed_out_total")
+    );
+}
+
+#[test]
+fn heartbeats_keep_connections_open() {
+    let sink = CaptureSink::default();
+    let (collector, socket) = collector("idle-heartbeat", &sink);
+    let _running = start(collector);
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || metrics::gauge!("in_flight").set(2.0));
+    for _ in 0..8 {
+        std::thread::sleep(Duration::from_millis(50));
+        recorder.heartbeat().unwrap();
+    }
+
+    assert!(sink.wait_for_removed(0).is_empty());
+    assert!(
+        sink.wait_for_metrics(1)
+            .iter()
+            .all(|m| m.name != "metrics_ipc_collector_connections_timed_out_total")
+    );
+}
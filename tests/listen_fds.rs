@@ -0,0 +1,52 @@
+//! Checks that the collector takes over the sockets systemd passes through `LISTEN_FDS`.
+//!
+//! Kept apart from the other activation tests: the socket has to sit at descriptor 3 and the
+//! environment is changed, so nothing else may run in this process at the same time.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, socket_dir, start};
+use metrics_ipc_collector::IPCCollector;
+use std::os::{fd::IntoRawFd, unix::net::UnixListener};
+
+#[test]
+fn takes_sockets_passed_by_the_service_manager() {
+    let dir = socket_dir("listen-fds");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+    let listener = UnixListener::bind(&socket).unwrap().into_raw_fd();
+    // SAFETY: descriptor 3 is only used by this test. Both `dup2` and clearing the flags leave it
+    // inheritable, like a descriptor passed by the service manager.
+    unsafe {
+        if listener == 3 {
+            libc::fcntl(3, libc::F_SETFD, 0);
+        } else {
+            assert_eq!(libc::dup2(listener, 3), 3);
+            libc::close(listener);
+        }
+    }
+    // SAFETY: no other thread reads the environment while this test runs.
+    unsafe {
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", "1");
+        std::env::set_var("LISTEN_FDNAMES", "collector");
+    }
+
+    let collector = IPCCollector::from_listen_fds().unwrap();
+
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        assert!(std::env::var_os(var).is_none(), "{var} is still set");
+    }
+    // SAFETY: reading the flags of a descriptor has no preconditions.
+    let flags = unsafe { libc::fcntl(3, libc::F_GETFD) };
+    assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+
+    let sink = CaptureSink::default();
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("activated_total").increment(1);
+    });
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "activated_total");
+}
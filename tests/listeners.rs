@@ -0,0 +1,68 @@
+//! Checks that one collector serves several sockets, each with settings of its own.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, socket_dir, start};
+use interprocess::local_socket::{GenericFilePath, Stream, prelude::*};
+use metrics_ipc_collector::{IPCCollector, ListenerConfig};
+use std::{io::Read, os::unix::fs::MetadataExt, time::Duration};
+
+#[test]
+fn labels_metrics_by_listener() {
+    let dir = socket_dir("listeners-labels");
+    let system = dir.join("system.sock");
+    let user = dir.join("user.sock");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&system)
+            .socket_dir_mode(0o755)
+            .label("tier", "system")
+            .listener(
+                ListenerConfig::default()
+                    .socket_file(&user)
+                    .label("tier", "user"),
+            )
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    for (socket, value) in [(&system, 1), (&user, 2)] {
+        let recorder = connect_recorder(socket);
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("jobs_total", "tier" => "spoofed").increment(value);
+        });
+    }
+
+    let mut metrics = sink.wait_for_metrics(2);
+    metrics.sort_by(|a, b| a.labels["tier"].cmp(&b.labels["tier"]));
+    let tiers: Vec<_> = metrics.iter().map(|m| m.labels["tier"].as_str()).collect();
+    assert_eq!(tiers, ["system", "user"]);
+}
+
+#[test]
+fn authorizes_peers_by_listener() {
+    let dir = socket_dir("listeners-auth");
+    std::fs::create_dir_all(&dir).unwrap();
+    let own_uid = std::fs::metadata(&dir).unwrap().uid();
+    let privileged = dir.join("privileged.sock");
+    let open = dir.join("open.sock");
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&privileged)
+            .allow_uid(own_uid.wrapping_add(1))
+            .listener(ListenerConfig::default().socket_file(&open)),
+    );
+
+    let connect = |socket: &std::path::Path| {
+        let stream = Stream::connect(socket.to_fs_name::<GenericFilePath>().unwrap()).unwrap();
+        stream
+            .set_recv_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        stream
+    };
+    // Rejected connections are closed, accepted ones stay open until the read times out.
+    assert_eq!(connect(&privileged).read(&mut [0; 1]).unwrap(), 0);
+    assert!(connect(&open).read(&mut [0; 1]).is_err());
+}
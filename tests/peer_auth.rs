@@ -0,0 +1,55 @@
+//! Checks that the collector closes connections from peers missing from its allowlist.
+#![cfg(unix)]
+
+mod common;
+
+use common::{socket_dir, start};
+use interprocess::local_socket::{GenericFilePath, Stream, prelude::*};
+use metrics_ipc_collector::IPCCollector;
+use std::{io::Read, os::unix::fs::MetadataExt, path::Path, time::Duration};
+
+fn connect(socket: &Path) -> Stream {
+    let name = socket.to_fs_name::<GenericFilePath>().unwrap();
+    let stream = Stream::connect(name).unwrap();
+    stream
+        .set_recv_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    stream
+}
+
+fn own_uid(dir: &Path) -> u32 {
+    std::fs::metadata(dir).unwrap().uid()
+}
+
+#[test]
+fn rejects_peer_not_in_allowlist() {
+    let dir = socket_dir("auth-reject");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .allow_uid(own_uid(&dir).wrapping_add(1)),
+    );
+
+    let mut stream = connect(&socket);
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn accepts_peer_in_allowlist() {
+    let dir = socket_dir("auth-accept");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .allow_uid(own_uid(&dir)),
+    );
+
+    // The collector never writes, so an accepted connection stays open until the read times out.
+    let mut stream = connect(&socket);
+    assert!(stream.read(&mut [0; 1]).is_err());
+}
@@ -0,0 +1,58 @@
+//! Checks that the collector labels metrics with the credentials of the process sending them.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use metrics_ipc_collector::PeerLabel;
+
+#[test]
+fn labels_metrics_with_peer_credentials() {
+    let (collector, socket) = test_collector("peer-credentials");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .peer_labels(&[
+                PeerLabel::Pid,
+                PeerLabel::Uid,
+                PeerLabel::Gid,
+                PeerLabel::Exe,
+            ])
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("requests", "pid" => "spoofed").increment(1);
+    });
+
+    let metrics = sink.wait_for_metrics(1);
+    let labels = &metrics[0].labels;
+    assert_eq!(labels["pid"], std::process::id().to_string());
+    // SAFETY: Neither call has preconditions.
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    assert_eq!(labels["uid"], uid.to_string());
+    assert_eq!(labels["gid"], gid.to_string());
+    if cfg!(target_os = "linux") {
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(
+            labels["exe"],
+            exe.file_name().unwrap().to_string_lossy().as_ref()
+        );
+    } else {
+        assert!(!labels.contains_key("exe"));
+    }
+}
+
+#[test]
+fn leaves_metrics_unlabeled_by_default() {
+    let (collector, socket) = test_collector("peer-labels-none");
+    let sink = CaptureSink::default();
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || metrics::counter!("requests").increment(1));
+
+    assert!(sink.wait_for_metrics(1)[0].labels.is_empty());
+}
@@ -0,0 +1,40 @@
+//! Checks that the default sink zeroes gauges removed by the disconnect policy, as the global
+//! recorder cannot forget them.
+#![cfg(unix)]
+
+mod common;
+
+use common::{connect_recorder, start, test_collector};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use metrics_ipc_collector::DisconnectPolicy;
+use std::time::{Duration, Instant};
+
+/// Waits up to five seconds for the rendered metrics to contain `line`.
+fn wait_for_line(handle: &PrometheusHandle, line: &str) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if handle.render().lines().any(|l| l == line) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn zeroes_removed_gauges_in_the_global_recorder() {
+    let handle = PrometheusBuilder::new().install_recorder().unwrap();
+    let (collector, socket) = test_collector("recorder-sink");
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .disconnect_policy(DisconnectPolicy::Remove),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || metrics::gauge!("in_flight").set(3.0));
+    assert!(wait_for_line(&handle, "in_flight 3"));
+
+    drop(recorder);
+    assert!(wait_for_line(&handle, "in_flight 0"));
+}
@@ -0,0 +1,39 @@
+//! Checks that the collector describes every metric received so far again on request.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+
+#[test]
+fn redescribes_cached_metadata() {
+    let (collector, socket) = test_collector("redescribe");
+    let sink = CaptureSink::default();
+    let running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_histogram!("latency", "Request latency.");
+        metrics::describe_counter!("requests", "Requests.");
+        metrics::describe_histogram!(
+            "latency",
+            metrics::Unit::Seconds,
+            "Time taken to serve a request."
+        );
+    });
+    assert_eq!(sink.wait_for_metadata(3).len(), 3);
+
+    running.handle.redescribe();
+    let metadata = sink.wait_for_metadata(5);
+    let redescribed: Vec<_> = metadata[3..]
+        .iter()
+        .map(|m| (m.name.as_str(), m.description.as_str(), m.unit.as_deref()))
+        .collect();
+    assert_eq!(
+        redescribed,
+        [
+            ("latency", "Time taken to serve a request.", Some("seconds")),
+            ("requests", "Requests.", None),
+        ]
+    );
+}
@@ -0,0 +1,111 @@
+//! Checks that relabeling rules rewrite metrics and metadata before they reach the sink.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, socket_dir, start, test_collector};
+use metrics_ipc_collector::{IPCCollector, RelabelAction, RelabelRule, RelabelRules};
+
+#[test]
+fn applies_rules_in_order() {
+    let (collector, socket) = test_collector("relabel");
+    let sink = CaptureSink::default();
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .relabel(
+                RelabelRule::new(RelabelAction::Replace, "http_(.*)")
+                    .unwrap()
+                    .source_labels(&["__name__"])
+                    .target_label("__name__")
+                    .replacement("web_$1"),
+            )
+            .relabel(
+                RelabelRule::new(RelabelAction::Replace, "(.*);(.*)")
+                    .unwrap()
+                    .source_labels(&["method", "path"])
+                    .target_label("route")
+                    .replacement("$1 $2"),
+            )
+            .relabel(RelabelRule::new(RelabelAction::LabelDrop, "method|path").unwrap())
+            .relabel(
+                RelabelRule::new(RelabelAction::Drop, "debug_.*")
+                    .unwrap()
+                    .source_labels(&["__name__"]),
+            )
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_counter!("http_requests", "Requests served.");
+        metrics::counter!("debug_loops").increment(1);
+        metrics::counter!("http_requests", "method" => "GET", "path" => "/").increment(1);
+    });
+
+    let metadata = sink.wait_for_metadata(1);
+    assert_eq!(metadata[0].name, "web_requests");
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "web_requests");
+    assert_eq!(
+        metrics[0].labels,
+        [("route".to_string(), "GET /".to_string())].into()
+    );
+}
+
+#[test]
+fn loads_rules_from_a_file() {
+    let dir = socket_dir("relabel_file");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("relabel.toml");
+    std::fs::write(
+        &config,
+        r#"
+[[rules]]
+action = "keep"
+source_labels = ["__name__"]
+regex = "app_.*"
+
+[[rules]]
+action = "labelmap"
+regex = "k8s_(.*)"
+replacement = "$1"
+"#,
+    )
+    .unwrap();
+
+    let socket = dir.join("collector.sock");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .self_telemetry(false)
+            .relabel_rules(RelabelRules::from_file(&config).unwrap())
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::gauge!("other_gauge").set(1.0);
+        metrics::gauge!("app_gauge", "k8s_pod" => "worker-0").set(2.0);
+    });
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "app_gauge");
+    assert_eq!(metrics[0].labels["pod"], "worker-0");
+    assert_eq!(metrics[0].labels["k8s_pod"], "worker-0");
+}
+
+#[test]
+fn rejects_invalid_rules() {
+    assert!(RelabelRule::new(RelabelAction::Keep, "(unclosed").is_err());
+    assert!(
+        "[[rules]]\naction = \"explode\""
+            .parse::<RelabelRules>()
+            .is_err()
+    );
+    assert!("[[rules]]\nregex = \"[\"".parse::<RelabelRules>().is_err());
+}
@@ -0,0 +1,191 @@
+//! Checks that a relaying collector forwards to an upstream collector.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, socket_dir, start};
+use metrics_ipc_collector::{DisconnectPolicy, IPCCollector, MetricOperation, RelaySinkBuilder};
+use std::{io::Read, os::unix::net::UnixListener, time::Duration};
+
+#[test]
+fn forwards_events_with_hop_labels() {
+    let dir = socket_dir("relay");
+    let upstream_socket = dir.join("node.sock");
+    let relay_socket = dir.join("container.sock");
+    let upstream = CaptureSink::default();
+    let _upstream = start(
+        IPCCollector::default()
+            .socket_file(&upstream_socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .sink(upstream.clone()),
+    );
+    let _relay = start(
+        IPCCollector::default()
+            .socket_file(&relay_socket)
+            .self_telemetry(false)
+            .sink(
+                RelaySinkBuilder::default()
+                    .socket_file(&upstream_socket)
+                    .hop_label("container", "a")
+                    .build()
+                    .unwrap(),
+            ),
+    );
+
+    let recorder = connect_recorder(&relay_socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_histogram!("latency", "Request latency.");
+        metrics::histogram!("latency", "route" => "/").record(0.5);
+    });
+
+    assert_eq!(upstream.wait_for_metadata(1)[0].name, "latency");
+    let metric = &upstream.wait_for_metrics(1)[0];
+    assert_eq!(metric.name, "latency");
+    assert_eq!(metric.labels["route"], "/");
+    assert_eq!(metric.labels["container"], "a");
+}
+
+#[test]
+fn pre_aggregates_counters_and_gauges() {
+    let dir = socket_dir("relay_aggregate");
+    let upstream_socket = dir.join("node.sock");
+    let relay_socket = dir.join("container.sock");
+    let upstream = CaptureSink::default();
+    let _upstream = start(
+        IPCCollector::default()
+            .socket_file(&upstream_socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .sink(upstream.clone()),
+    );
+    let _relay = start(
+        IPCCollector::default()
+            .socket_file(&relay_socket)
+            .self_telemetry(false)
+            .sink(
+                RelaySinkBuilder::default()
+                    .socket_file(&upstream_socket)
+                    .aggregate(Duration::from_secs(1))
+                    .build()
+                    .unwrap(),
+            ),
+    );
+
+    let recorder = connect_recorder(&relay_socket);
+    metrics::with_local_recorder(&recorder, || {
+        for _ in 0..3 {
+            metrics::counter!("jobs").increment(2);
+        }
+        metrics::gauge!("queue_depth").set(5.0);
+        metrics::gauge!("queue_depth").decrement(2.0);
+    });
+
+    let mut metrics = upstream.wait_for_metrics(2);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(upstream.wait_for_metrics(2).len(), 2);
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+    assert!(matches!(
+        metrics[0].operation,
+        MetricOperation::IncrementCounter(6)
+    ));
+    assert!(matches!(
+        metrics[1].operation,
+        MetricOperation::SetGauge(v) if (v - 3.0).abs() < f64::EPSILON
+    ));
+}
+
+#[test]
+fn forwards_expired_series_before_the_flush() {
+    let dir = socket_dir("relay_expiry");
+    let upstream_socket = dir.join("node.sock");
+    let relay_socket = dir.join("container.sock");
+    let upstream = CaptureSink::default();
+    let _upstream = start(
+        IPCCollector::default()
+            .socket_file(&upstream_socket)
+            .socket_dir_mode(0o700)
+            .self_telemetry(false)
+            .sink(upstream.clone()),
+    );
+    let _relay = start(
+        IPCCollector::default()
+            .socket_file(&relay_socket)
+            .self_telemetry(false)
+            .disconnect_policy(DisconnectPolicy::Remove)
+            .sink(
+                RelaySinkBuilder::default()
+                    .socket_file(&upstream_socket)
+                    .aggregate(Duration::from_millis(500))
+                    .build()
+                    .unwrap(),
+            ),
+    );
+
+    // A short-lived worker exits well before the relay flushes.
+    let recorder = connect_recorder(&relay_socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("jobs").increment(3);
+        metrics::gauge!("workers").set(1.0);
+    });
+    drop(recorder);
+
+    let mut metrics = upstream.wait_for_metrics(2);
+    std::thread::sleep(Duration::from_millis(600));
+    assert_eq!(upstream.wait_for_metrics(2).len(), 2);
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+    assert!(matches!(
+        metrics[0].operation,
+        MetricOperation::IncrementCounter(3)
+    ));
+    assert!(matches!(
+        metrics[1].operation,
+        MetricOperation::SetGauge(v) if v == 0.0
+    ));
+}
+
+#[test]
+fn reconnects_and_describes_metrics_again() {
+    let dir = socket_dir("relay_reconnect");
+    std::fs::create_dir_all(&dir).unwrap();
+    let upstream_socket = dir.join("node.sock");
+    let relay_socket = dir.join("container.sock");
+
+    // The first upstream is a plain socket that closes the relay's connection after one read.
+    let first = UnixListener::bind(&upstream_socket).unwrap();
+    let relay = RelaySinkBuilder::default()
+        .socket_file(&upstream_socket)
+        .client_id("container-a")
+        .build()
+        .unwrap();
+    let (mut connection, _) = first.accept().unwrap();
+    let _relay = start(
+        IPCCollector::default()
+            .socket_file(&relay_socket)
+            .self_telemetry(false)
+            .sink(relay),
+    );
+    let recorder = connect_recorder(&relay_socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_counter!("jobs", "Jobs run.")
+    });
+    assert!(connection.read(&mut [0; 1024]).unwrap() > 0);
+    drop((connection, first));
+
+    // A collector taking the socket over gets everything relayed from then on.
+    let upstream = CaptureSink::default();
+    let _upstream = start(
+        IPCCollector::default()
+            .socket_file(&upstream_socket)
+            .self_telemetry(false)
+            .sink(upstream.clone()),
+    );
+    metrics::with_local_recorder(&recorder, || metrics::counter!("jobs").increment(2));
+
+    assert_eq!(upstream.wait_for_metadata(1)[0].name, "jobs");
+    let metrics = upstream.wait_for_metrics(1);
+    assert!(matches!(
+        metrics[..],
+        [ref metric] if matches!(metric.operation, MetricOperation::IncrementCounter(2))
+    ));
+}
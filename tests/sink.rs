@@ -0,0 +1,119 @@
+//! Checks that the collector dispatches received events to its configured sink.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, start, test_collector};
+use interprocess::local_socket::{GenericFilePath, Stream, prelude::*};
+use metrics::Unit;
+use metrics_ipc_collector::{MetricData, MetricEvent, MetricKind, MetricOperation, PeerLabel};
+use std::{collections::BTreeMap, io::Write, time::Duration};
+
+#[test]
+fn dispatches_metrics_and_metadata_to_sink() {
+    let (collector, socket) = test_collector("sink");
+    let sink = CaptureSink::default();
+
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_gauge!("queue_depth", Unit::Count, "Jobs waiting");
+        metrics::gauge!("queue_depth", "queue" => "high").set(3.0);
+    });
+
+    let metadata = sink.wait_for_metadata(1);
+    assert_eq!(metadata[0].name, "queue_depth");
+    assert_eq!(metadata[0].kind, MetricKind::Gauge);
+    assert_eq!(metadata[0].unit.as_deref(), Some("count"));
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics[0].name, "queue_depth");
+    assert_eq!(metrics[0].labels["queue"], "high");
+    assert!(matches!(metrics[0].operation, MetricOperation::SetGauge(v) if v == 3.0));
+}
+
+#[test]
+fn adds_peer_labels() {
+    let (collector, socket) = test_collector("peer-labels");
+    let sink = CaptureSink::default();
+
+    let _running = start(
+        collector
+            .peer_labels(&[PeerLabel::Pid])
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("requests", "pid" => "spoofed").increment(1);
+    });
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics[0].labels["pid"], std::process::id().to_string());
+}
+
+#[test]
+fn reports_self_telemetry() {
+    let (collector, socket) = test_collector("telemetry");
+    let sink = CaptureSink::default();
+
+    let _running = start(collector.sink(sink.clone()));
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("requests").increment(1);
+    });
+    drop(recorder);
+
+    let metadata = sink.wait_for_metadata(8);
+    assert!(
+        metadata
+            .iter()
+            .all(|m| m.name.starts_with("metrics_ipc_collector_"))
+    );
+
+    // accepted + active, bytes + decoded, the counter itself, then closed + active.
+    let metrics = sink.wait_for_metrics(7);
+    let names: Vec<_> = metrics.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "metrics_ipc_collector_connections_accepted_total",
+            "metrics_ipc_collector_connections_active",
+            "metrics_ipc_collector_bytes_received_total",
+            "metrics_ipc_collector_frames_decoded_total",
+            "requests",
+            "metrics_ipc_collector_connections_closed_total",
+            "metrics_ipc_collector_connections_active",
+        ]
+    );
+}
+
+#[test]
+fn receives_frames_split_across_writes() {
+    let (collector, socket) = test_collector("split-frame");
+    let sink = CaptureSink::default();
+
+    let _running = start(collector.self_telemetry(false).sink(sink.clone()));
+
+    let mut frame = Vec::try_from(MetricEvent::Metric(MetricData {
+        name: "requests".to_string(),
+        labels: BTreeMap::new(),
+        operation: MetricOperation::IncrementCounter(1),
+    }))
+    .unwrap();
+    frame.push(b'\n');
+    let (head, tail) = frame.split_at(frame.len() / 2);
+
+    let name = socket.to_fs_name::<GenericFilePath>().unwrap();
+    let mut stream = Stream::connect(name).unwrap();
+    stream.write_all(head).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    stream.write_all(tail).unwrap();
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "requests");
+}
@@ -0,0 +1,110 @@
+//! Checks that socket permission options are applied to the socket file created by the collector,
+//! and only to socket files.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, socket_dir, start};
+use interprocess::local_socket::{GenericNamespaced, NameType, prelude::*};
+use metrics_ipc_collector::{IPCCollector, IPCRecorder};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+fn mode_bits(path: &std::path::Path) -> u32 {
+    std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn applies_socket_mode() {
+    let dir = socket_dir("mode");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .socket_mode(0o600),
+    );
+
+    assert_eq!(mode_bits(&socket), 0o600);
+}
+
+#[test]
+fn applies_datagram_socket_mode() {
+    let dir = socket_dir("datagram-mode");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.dgram");
+
+    let _running = start(IPCCollector::default().datagram(&socket).socket_mode(0o620));
+
+    assert_eq!(mode_bits(&socket), 0o620);
+}
+
+#[test]
+fn creates_parent_directory_with_mode() {
+    let dir = socket_dir("dir-mode").join("nested");
+    let socket = dir.join("collector.sock");
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .socket_mode(0o660)
+            .socket_dir_mode(0o710),
+    );
+
+    assert_eq!(mode_bits(&dir), 0o710);
+    assert_eq!(mode_bits(&socket), 0o660);
+}
+
+#[test]
+fn leaves_existing_directories_alone() {
+    let dir = socket_dir("dir-existing");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(dir.join("collector.sock"))
+            .socket_dir_mode(0o700),
+    );
+
+    assert_eq!(mode_bits(&dir), 0o755);
+}
+
+#[test]
+fn socket_names_stay_namespaced() {
+    if !GenericNamespaced::is_supported() {
+        return;
+    }
+    let dir = socket_dir("namespaced");
+    let name = dir.join("collector.sock");
+    let sink = CaptureSink::default();
+    let _running = start(
+        IPCCollector::default()
+            .socket(name.to_str().unwrap())
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+
+    assert!(!name.exists());
+    let name = name.to_str().unwrap().to_ns_name::<GenericNamespaced>();
+    let recorder = IPCRecorder::new(LocalSocketStream::connect(name.unwrap()).unwrap());
+    metrics::with_local_recorder(&recorder, || metrics::counter!("namespaced").increment(1));
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "namespaced");
+}
+
+#[test]
+fn applies_socket_group() {
+    let dir = socket_dir("group");
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("collector.sock");
+    // Any group owning a file we created is one we are allowed to chown to.
+    let gid = std::fs::metadata(&dir).unwrap().gid();
+
+    let _running = start(
+        IPCCollector::default()
+            .socket_file(&socket)
+            .socket_group(gid),
+    );
+
+    assert_eq!(std::fs::metadata(&socket).unwrap().gid(), gid);
+}
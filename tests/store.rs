@@ -0,0 +1,77 @@
+//! Checks that the memory store holds the current value of every collected series.
+#![cfg(unix)]
+
+mod common;
+
+use common::{connect_recorder, start, test_collector};
+use metrics::Unit;
+use metrics_ipc_collector::{DisconnectPolicy, MemoryStore, SeriesValue, Snapshot};
+use std::time::{Duration, Instant};
+
+/// Takes snapshots until `done` returns `true`, failing the test after five seconds.
+fn wait_for_snapshot(store: &MemoryStore, done: impl Fn(&Snapshot) -> bool) -> Snapshot {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let snapshot = store.snapshot();
+        if done(&snapshot) {
+            return snapshot;
+        }
+        assert!(Instant::now() < deadline, "timed out: {snapshot:?}");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn stores_current_values_and_metadata() {
+    let (collector, socket) = test_collector("store");
+    let store = MemoryStore::new(2);
+    let _running = start(
+        collector
+            .self_telemetry(false)
+            .disconnect_policy(DisconnectPolicy::Remove)
+            .sink(store.clone()),
+    );
+
+    let recorder = connect_recorder(&socket);
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_histogram!("latency", Unit::Seconds, "Request latency.");
+        metrics::counter!("requests", "code" => "200").increment(2);
+        metrics::counter!("requests", "code" => "200").increment(3);
+        metrics::gauge!("in_flight").set(4.0);
+        metrics::gauge!("in_flight").decrement(1.0);
+        for value in [1.0, 5.0, 3.0] {
+            metrics::histogram!("latency").record(value);
+        }
+    });
+
+    let snapshot = wait_for_snapshot(&store, |s| {
+        s.get("latency", &[])
+            .is_some_and(|l| matches!(&l.value, SeriesValue::Histogram(d) if d.count == 3))
+    });
+    assert_eq!(snapshot.metadata.len(), 1);
+    assert!(matches!(
+        snapshot.get("requests", &[("code", "200")]).unwrap().value,
+        SeriesValue::Counter(5)
+    ));
+    assert!(matches!(
+        snapshot.get("in_flight", &[]).unwrap().value,
+        SeriesValue::Gauge(v) if (v - 3.0).abs() < f64::EPSILON
+    ));
+
+    let latency = snapshot.get("latency", &[]).unwrap();
+    assert_eq!(latency.unit.as_deref(), Some("seconds"));
+    assert_eq!(latency.description.as_deref(), Some("Request latency."));
+    let SeriesValue::Histogram(distribution) = &latency.value else {
+        panic!("expected a histogram, got {:?}", latency.value);
+    };
+    assert!((distribution.sum - 9.0).abs() < f64::EPSILON);
+    assert!((distribution.min - 1.0).abs() < f64::EPSILON);
+    assert!((distribution.max - 5.0).abs() < f64::EPSILON);
+    // Only the two most recent samples are kept.
+    assert_eq!(distribution.samples, [5.0, 3.0]);
+    assert_eq!(distribution.quantile(1.0), Some(5.0));
+
+    drop(recorder);
+    let snapshot = wait_for_snapshot(&store, |s| s.series.is_empty());
+    assert_eq!(snapshot.metadata.len(), 1);
+}
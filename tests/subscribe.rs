@@ -0,0 +1,45 @@
+//! Checks that subscriptions receive the raw events arriving at the collector.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, connect_recorder, next_event, start, test_collector};
+use metrics_ipc_collector::{MetricEvent, MetricOperation};
+use std::time::SystemTime;
+
+#[test]
+fn delivers_decoded_events_before_filtering() {
+    let (collector, socket) = test_collector("subscribe");
+    let sink = CaptureSink::default();
+    let running = start(
+        collector
+            .self_telemetry(false)
+            .deny_metrics("hidden")
+            .sink(sink.clone()),
+    );
+    let mut subscription = running.handle.subscribe(16);
+    let before = SystemTime::now();
+
+    let first = connect_recorder(&socket);
+    let second = connect_recorder(&socket);
+    metrics::with_local_recorder(&first, || metrics::counter!("hidden").increment(1));
+    let received = next_event(&running, &mut subscription).unwrap();
+    metrics::with_local_recorder(&second, || metrics::gauge!("shown").set(2.0));
+    let next = next_event(&running, &mut subscription).unwrap();
+
+    let MetricEvent::Metric(metric) = received.event else {
+        panic!("expected a metric, got {:?}", received.event);
+    };
+    assert_eq!(metric.name, "hidden");
+    assert!(received.received_at >= before);
+
+    let MetricEvent::Metric(metric) = next.event else {
+        panic!("expected a metric, got {:?}", next.event);
+    };
+    assert_eq!(metric.name, "shown");
+    assert!(matches!(metric.operation, MetricOperation::SetGauge(_)));
+    assert_ne!(received.connection_id, next.connection_id);
+
+    // The sink still only sees what passed the filters.
+    assert_eq!(sink.wait_for_metrics(1)[0].name, "shown");
+}
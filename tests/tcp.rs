@@ -0,0 +1,45 @@
+//! Checks that the collector accepts clients over TCP.
+#![cfg(unix)]
+
+mod common;
+
+use common::{CaptureSink, start};
+use metrics_ipc_collector::{IPCCollector, IPCRecorder};
+use std::{io::Read, net::TcpStream, time::Duration};
+
+#[test]
+fn receives_metrics_over_tcp() {
+    let sink = CaptureSink::default();
+    let running = start(
+        IPCCollector::default()
+            .tcp(0)
+            .self_telemetry(false)
+            .sink(sink.clone()),
+    );
+    let addr = running.handle.tcp_addr().unwrap();
+    assert!(addr.ip().is_loopback());
+
+    let recorder = IPCRecorder::from_tcp(TcpStream::connect(addr).unwrap());
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_gauge!("queue_depth", "Jobs waiting.");
+        metrics::gauge!("queue_depth", "queue" => "email").set(3.0);
+    });
+
+    let metrics = sink.wait_for_metrics(1);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "queue_depth");
+    assert_eq!(metrics[0].labels["queue"], "email");
+    assert_eq!(sink.wait_for_metadata(1)[0].description, "Jobs waiting.");
+}
+
+#[test]
+fn rejects_tcp_peers_when_an_allowlist_is_set() {
+    let running = start(IPCCollector::default().tcp(0).allow_uid(0));
+    let addr = running.handle.tcp_addr().unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}